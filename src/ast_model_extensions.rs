@@ -1,6 +1,8 @@
-use syntex_pos::Span;
+use ast_node::{AstNode, Property};
+use node_filter::NodeFilter;
+use span_index::SpanIndex;
 use gtk::prelude::*;
-use gtk::{TreeModel, TreeIter, ListStore, TreeStore, TreeModelExt, ListStoreExtManual, TreeStoreExtManual};
+use gtk::{TreeModel, TreeIter, TreePath, TreeStore, TreeModelExt, TreeStoreExtManual};
 
 pub(crate) enum AstModelColumns {
     Type = 0,
@@ -9,14 +11,24 @@ pub(crate) enum AstModelColumns {
     HasSpan = 3,
     Lo = 4,
     Hi = 5,
+    Expanded = 6,
+    CallSiteLo = 7,
+    CallSiteHi = 8,
+    /// Cleared for rows hidden by the search filter.
+    Visible = 9,
 }
 
 pub(crate) trait AstModelExt {
     fn get_type(&self, iter: &TreeIter) -> String;
     fn get_kind(&self, iter: &TreeIter) -> String;
-    fn get_properties_list(&self, iter: &TreeIter) -> ListStore;
+    fn get_properties_list(&self, iter: &TreeIter) -> TreeStore;
     fn get_span(&self, iter: &TreeIter) -> Option<(u32, u32)>;
-    fn find_node_by_pos(&self, pos: i32) -> Option<TreeIter>;
+    fn get_call_site(&self, iter: &TreeIter) -> Option<(u32, u32)>;
+    fn get_property_value(&self, iter: &TreeIter, name: &str) -> Option<String>;
+    fn find_node_by_pos(&self, index: &SpanIndex, pos: u32) -> Vec<TreeIter>;
+    fn find_node_by_span(&self, span: (u32, u32)) -> Option<TreeIter>;
+    fn get_ancestry(&self, iter: &TreeIter) -> Vec<(String, String)>;
+    fn find_node_by_ancestry(&self, ancestry: &[(String, String)], lo: u32) -> Option<TreeIter>;
 }
 
 macro_rules! get_ast_model_value {
@@ -26,15 +38,19 @@ macro_rules! get_ast_model_value {
     }
 }
 
-fn _is_in_span<T: AstModelExt + TreeModelExt>(model: &T, iter: &TreeIter, pos: u32) -> bool {
-    let ty = model.get_type(iter);
-    if let Some((lo, hi)) = model.get_span(iter) {
-        let res = (lo <= pos) && (pos <= hi);
-        println!("{} in {}:{} = {}", pos, lo, hi, res);
-        res
-    }
-    else {
-        false
+/// `TreePath` of the node at the given child indices.
+pub(crate) fn tree_path(indices: &[usize]) -> TreePath {
+    let path = indices.iter()
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>()
+        .join(":");
+    TreePath::new_from_string(&path)
+}
+
+fn _is_in_span<T: AstModelExt + TreeModelExt>(model: &T, iter: &TreeIter, span: (u32, u32)) -> bool {
+    match model.get_span(iter) {
+        Some((lo, hi)) => lo <= span.0 && span.1 <= hi,
+        None => false,
     }
 }
 
@@ -50,26 +66,47 @@ fn _compare_spans<T: AstModelExt + TreeModelExt>(model: &T, iter1: &TreeIter, it
     }
 }
 
-fn _find_node_by_pos<T: AstModelExt + TreeModelExt>(model: &T, iter: &TreeIter, pos: i32) -> Option<TreeIter> {
+fn _find_node_by_span<T: AstModelExt + TreeModelExt>(model: &T, iter: &TreeIter, span: (u32, u32)) -> Option<TreeIter> {
     let mut smallest = iter.clone();
     let len = model.iter_n_children(iter);
 
     for i in 0..len {
         let child = model.iter_nth_child(iter, i).expect("Could not get iter child");
-        if let Some(child_res) = _find_node_by_pos(model, &child, pos) {
+        if let Some(child_res) = _find_node_by_span(model, &child, span) {
             if _compare_spans(model, &smallest, &child_res) {
                 smallest = child_res;
             }
         }
     }
 
-    if _is_in_span(model, &smallest, pos as u32) {
+    if _is_in_span(model, &smallest, span) {
         return Some(smallest);
     }
 
     None
 }
 
+fn _find_node_by_ancestry<T: AstModelExt + TreeModelExt>(model: &T, parent: Option<&TreeIter>,
+                                                         ancestry: &[(String, String)], lo: u32) -> Option<TreeIter> {
+    let (&(ref ty, ref kind), rest) = ancestry.split_first()?;
+    let mut child = model.iter_children(parent);
+    while let Some(iter) = child {
+        if model.get_type(&iter) == *ty && model.get_kind(&iter) == *kind {
+            let found = if rest.is_empty() {
+                if model.get_span(&iter).map_or(false, |span| span.0 == lo) { Some(iter.clone()) } else { None }
+            }
+            else {
+                _find_node_by_ancestry(model, Some(&iter), rest, lo)
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        child = if model.iter_next(&iter) { Some(iter) } else { None };
+    }
+    None
+}
+
 impl<O: IsA<TreeModel> + TreeModelExt> AstModelExt for O {
     fn get_type(&self, iter: &TreeIter) -> String {
         get_ast_model_value!(self, iter, Type, String)
@@ -79,8 +116,8 @@ impl<O: IsA<TreeModel> + TreeModelExt> AstModelExt for O {
         get_ast_model_value!(self, iter, Kind, String)
     }
 
-    fn get_properties_list(&self, iter: &TreeIter) -> ListStore {
-        get_ast_model_value!(self, iter, Properties, ListStore)
+    fn get_properties_list(&self, iter: &TreeIter) -> TreeStore {
+        get_ast_model_value!(self, iter, Properties, TreeStore)
     }
 
     fn get_span(&self, iter: &TreeIter) -> Option<(u32, u32)> {
@@ -95,16 +132,120 @@ impl<O: IsA<TreeModel> + TreeModelExt> AstModelExt for O {
         }
     }
 
-    fn find_node_by_pos(&self, pos: i32) -> Option<TreeIter> {
-        println!("===========================================");
-        let first = self.get_iter_first()?;
-        _find_node_by_pos(self, &first, pos)
+    fn get_call_site(&self, iter: &TreeIter) -> Option<(u32, u32)> {
+        let expanded = get_ast_model_value!(self, iter, Expanded, bool);
+        if !expanded {
+            None
+        }
+        else {
+            let lo = get_ast_model_value!(self, iter, CallSiteLo, u32);
+            let hi = get_ast_model_value!(self, iter, CallSiteHi, u32);
+            Some((lo, hi))
+        }
+    }
+
+    /// Value of a top-level entry of the node's properties list.
+    fn get_property_value(&self, iter: &TreeIter, name: &str) -> Option<String> {
+        let properties = self.get_properties_list(iter);
+        let mut child = properties.get_iter_first();
+        while let Some(property) = child {
+            let property_name = properties.get_value(&property, AstPropertiesColumns::Name as i32)
+                .get::<String>().expect("Could not get value from TreeStore");
+            if property_name == name {
+                return properties.get_value(&property, AstPropertiesColumns::Value as i32).get::<String>();
+            }
+            child = if properties.iter_next(&property) { Some(property) } else { None };
+        }
+        None
+    }
+
+    /// The smallest node containing `pos` and all of its ancestors, starting
+    /// at a top-level row. Looked up in the index built from the nodes this
+    /// model was filled with; empty if no node contains `pos`.
+    fn find_node_by_pos(&self, index: &SpanIndex, pos: u32) -> Vec<TreeIter> {
+        let indices = match index.find(pos) {
+            Some(indices) => indices,
+            None => return vec![],
+        };
+        (1..indices.len() + 1)
+            .filter_map(|depth| self.get_iter(&tree_path(&indices[..depth])))
+            .collect()
+    }
+
+    /// The smallest node whose span contains all of `span`, under any of the
+    /// top-level rows.
+    fn find_node_by_span(&self, span: (u32, u32)) -> Option<TreeIter> {
+        let mut smallest: Option<TreeIter> = None;
+        let mut root = self.get_iter_first();
+        while let Some(iter) = root {
+            if let Some(found) = _find_node_by_span(self, &iter, span) {
+                let smaller = match smallest {
+                    Some(ref smallest) => _compare_spans(self, smallest, &found),
+                    None => true,
+                };
+                if smaller {
+                    smallest = Some(found);
+                }
+            }
+            root = if self.iter_next(&iter) { Some(iter) } else { None };
+        }
+        smallest
+    }
+
+    /// Type and Kind of the node and of each of its ancestors, outermost first.
+    fn get_ancestry(&self, iter: &TreeIter) -> Vec<(String, String)> {
+        let mut ancestry = vec![];
+        let mut current = Some(iter.clone());
+        while let Some(iter) = current {
+            ancestry.push((self.get_type(&iter), self.get_kind(&iter)));
+            current = self.iter_parent(&iter);
+        }
+        ancestry.reverse();
+        ancestry
+    }
+
+    /// The node starting at `lo` whose ancestry is `ancestry`, i.e. the same
+    /// node as in another tree of the same source, once `lo` is adjusted
+    /// for edits.
+    fn find_node_by_ancestry(&self, ancestry: &[(String, String)], lo: u32) -> Option<TreeIter> {
+        _find_node_by_ancestry(self, None, ancestry, lo)
     }
 }
 
 pub(crate) trait AstStoreExt {
     fn new_ast_store() -> TreeStore;
-    fn insert_node(&self, iter: Option<&TreeIter>, ty: &str, kind: &str, span: Option<Span>) -> TreeIter;
+    fn insert_node(&self, iter: Option<&TreeIter>, node: &AstNode) -> TreeIter;
+    fn insert_nodes(&self, iter: Option<&TreeIter>, nodes: &[AstNode]);
+    fn apply_filter(&self, filter: Option<&NodeFilter>) -> Vec<TreePath>;
+}
+
+/// Shows rows matching the filter and their ancestors; returns whether any
+/// row under `parent` stayed visible.
+fn _apply_filter(store: &TreeStore, parent: Option<&TreeIter>, filter: Option<&NodeFilter>, hits: &mut Vec<TreePath>) -> bool {
+    let mut any_visible = false;
+    let mut child = store.iter_children(parent);
+    while let Some(iter) = child {
+        let matched = match filter {
+            Some(filter) => {
+                let names: Vec<String> = ["Name", "Ident"].iter()
+                    .filter_map(|name| store.get_property_value(&iter, name))
+                    .collect();
+                filter.matches(&store.get_type(&iter), &store.get_kind(&iter), &names)
+            }
+            None => true,
+        };
+        if matched && filter.is_some() {
+            hits.push(store.get_path(&iter).expect("Could not get tree path"));
+        }
+
+        let descendant_visible = _apply_filter(store, Some(&iter), filter, hits);
+        let visible = matched || descendant_visible;
+        store.set_value(&iter, AstModelColumns::Visible as u32, &visible.to_value());
+        any_visible |= visible;
+
+        child = if store.iter_next(&iter) { Some(iter) } else { None };
+    }
+    any_visible
 }
 
 impl<O: IsA<TreeStore> + TreeStoreExtManual> AstStoreExt for O {
@@ -112,41 +253,75 @@ impl<O: IsA<TreeStore> + TreeStoreExtManual> AstStoreExt for O {
         TreeStore::new(&[
             String::static_type(),
             String::static_type(),
-            ListStore::static_type(),
+            TreeStore::static_type(),
             bool::static_type(),
             u32::static_type(),
-            u32::static_type()
+            u32::static_type(),
+            bool::static_type(),
+            u32::static_type(),
+            u32::static_type(),
+            bool::static_type()
         ])
     }
 
-    fn insert_node(&self, iter: Option<&TreeIter>, ty: &str, kind: &str, span: Option<Span>) -> TreeIter {
+    fn insert_node(&self, iter: Option<&TreeIter>, node: &AstNode) -> TreeIter {
         let mut cols: Vec<u32> = vec![
             AstModelColumns::Type as u32,
             AstModelColumns::Kind as u32,
             AstModelColumns::Properties as u32,
-            AstModelColumns::HasSpan as u32
+            AstModelColumns::HasSpan as u32,
+            AstModelColumns::Expanded as u32,
+            AstModelColumns::Visible as u32
         ];
-        let properties_store = ListStore::new_ast_properties_store();
-        properties_store.insert_property("Type", &ty);
-        properties_store.insert_property("Kind", &kind);
+        let properties_store = TreeStore::new_ast_properties_store();
+        properties_store.insert_property(None, "Type", &node.ty);
+        properties_store.insert_property(None, "Kind", &node.kind);
+        properties_store.insert_properties(None, &node.properties);
 
+        let has_span = node.span.is_some();
+        let expanded = node.expansion.is_some();
         let mut vals: Vec<&ToValue> = vec![
-            &ty,
-            &kind,
+            &node.ty,
+            &node.kind,
             &properties_store,
-            &false
+            &has_span,
+            &expanded,
+            &true
         ];
 
-        if let Some(ref span) = span {
+        if let Some((ref lo, ref hi)) = node.span {
             cols.push(AstModelColumns::Lo as u32);
             cols.push(AstModelColumns::Hi as u32);
 
-            vals[AstModelColumns::HasSpan as usize] = &true;
-            vals.push(&span.lo.0);
-            vals.push(&span.hi.0);
+            vals.push(lo);
+            vals.push(hi);
         }
 
-        self.insert_with_values(iter, None, &cols, &vals)
+        if let Some(ref expansion) = node.expansion {
+            cols.push(AstModelColumns::CallSiteLo as u32);
+            cols.push(AstModelColumns::CallSiteHi as u32);
+
+            vals.push(&expansion.call_site.0);
+            vals.push(&expansion.call_site.1);
+        }
+
+        let node_iter = self.insert_with_values(iter, None, &cols, &vals);
+        self.insert_nodes(Some(&node_iter), &node.children);
+        node_iter
+    }
+
+    fn insert_nodes(&self, iter: Option<&TreeIter>, nodes: &[AstNode]) {
+        for node in nodes {
+            self.insert_node(iter, node);
+        }
+    }
+
+    /// Updates the Visible column for `filter` and returns the paths of
+    /// matching rows in tree order. `None` shows every row.
+    fn apply_filter(&self, filter: Option<&NodeFilter>) -> Vec<TreePath> {
+        let mut hits = vec![];
+        _apply_filter(self.upcast_ref(), None, filter, &mut hits);
+        hits
     }
 }
 
@@ -156,19 +331,35 @@ pub(crate) enum AstPropertiesColumns {
 }
 
 pub(crate) trait AstPropertiesStoreExt {
-    fn new_ast_properties_store() -> ListStore;
-    fn insert_property(&self, name: &str, value: &str) -> TreeIter;
+    fn new_ast_properties_store() -> TreeStore;
+    fn insert_property(&self, iter: Option<&TreeIter>, name: &str, value: &str) -> TreeIter;
+    fn insert_properties(&self, iter: Option<&TreeIter>, properties: &[Property]);
+    fn copy_properties_store(&self) -> TreeStore;
+}
+
+fn _copy_properties<T: TreeModelExt>(from: &T, from_parent: Option<&TreeIter>, to: &TreeStore, to_parent: Option<&TreeIter>) {
+    let mut child = from.iter_children(from_parent);
+    while let Some(iter) = child {
+        let name = from.get_value(&iter, AstPropertiesColumns::Name as i32)
+            .get::<String>().expect("Could not get value from TreeStore");
+        let value = from.get_value(&iter, AstPropertiesColumns::Value as i32)
+            .get::<String>().expect("Could not get value from TreeStore");
+        let copy = to.insert_property(to_parent, &name, &value);
+        _copy_properties(from, Some(&iter), to, Some(&copy));
+
+        child = if from.iter_next(&iter) { Some(iter) } else { None };
+    }
 }
 
-impl<O: IsA<ListStore> + ListStoreExtManual> AstPropertiesStoreExt for O {
-    fn new_ast_properties_store() -> ListStore {
-        ListStore::new(&[
+impl<O: IsA<TreeStore> + TreeStoreExtManual + TreeModelExt> AstPropertiesStoreExt for O {
+    fn new_ast_properties_store() -> TreeStore {
+        TreeStore::new(&[
             String::static_type(),
             String::static_type()
         ])
     }
 
-    fn insert_property(&self, name: &str, value: &str) -> TreeIter {
+    fn insert_property(&self, iter: Option<&TreeIter>, name: &str, value: &str) -> TreeIter {
         let cols: Vec<u32> = vec![
             AstPropertiesColumns::Name as u32,
             AstPropertiesColumns::Value as u32
@@ -179,6 +370,19 @@ impl<O: IsA<ListStore> + ListStoreExtManual> AstPropertiesStoreExt for O {
             &value
         ];
 
-        self.insert_with_values(None, &cols, &vals)
+        self.insert_with_values(iter, None, &cols, &vals)
+    }
+
+    fn insert_properties(&self, iter: Option<&TreeIter>, properties: &[Property]) {
+        for property in properties {
+            let property_iter = self.insert_property(iter, &property.name, &property.value);
+            self.insert_properties(Some(&property_iter), &property.children);
+        }
+    }
+
+    fn copy_properties_store(&self) -> TreeStore {
+        let copy = TreeStore::new_ast_properties_store();
+        _copy_properties(self, None, &copy, None);
+        copy
     }
 }
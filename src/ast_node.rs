@@ -0,0 +1,85 @@
+use syntex_pos::Span;
+
+/// A named value describing a node. Structured values, like a function
+/// header, keep their parts as child properties.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Property {
+    pub name: String,
+    pub value: String,
+    pub children: Vec<Property>,
+}
+
+impl Property {
+    pub fn new(name: &str, value: &str) -> Property {
+        Property {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            children: vec![],
+        }
+    }
+
+    pub fn with_child(mut self, name: &str, value: &str) -> Property {
+        self.children.push(Property::new(name, value));
+        self
+    }
+}
+
+/// Where a node produced by macro expansion came from.
+#[derive(Clone, Debug)]
+pub(crate) struct Expansion {
+    /// Name of the outermost macro, e.g. `vec` for code generated by `vec!`.
+    pub macro_name: String,
+    /// Span of that macro's invocation in the written source.
+    pub call_site: (u32, u32),
+}
+
+impl Expansion {
+    fn from_span(mut span: Span) -> Option<Expansion> {
+        let mut expansion = None;
+        while let Some(info) = span.ctxt.outer().expn_info() {
+            span = info.call_site;
+            expansion = Some(Expansion {
+                macro_name: info.callee.name().to_string(),
+                call_site: (span.lo.0, span.hi.0),
+            });
+        }
+        expansion
+    }
+}
+
+/// A plain syntax tree node, independent of any GTK model.
+///
+/// `TreeVisitor` produces a forest of these, which can then be fed into a
+/// `TreeStore` for display or consumed directly by headless tools.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct AstNode {
+    pub ty: String,
+    pub kind: String,
+    pub span: Option<(u32, u32)>,
+    /// Set for nodes generated by macro expansion.
+    pub expansion: Option<Expansion>,
+    pub properties: Vec<Property>,
+    pub children: Vec<AstNode>,
+}
+
+impl AstNode {
+    pub fn new(ty: &str, kind: &str, span: Option<Span>) -> AstNode {
+        let expansion = span.and_then(Expansion::from_span);
+        let properties = match expansion {
+            Some(ref expansion) => vec![Property::new("ExpandedFrom", &format!("{}!", expansion.macro_name))],
+            None => vec![],
+        };
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            span: span.map(|s| (s.lo.0, s.hi.0)),
+            expansion: expansion,
+            properties: properties,
+            children: vec![],
+        }
+    }
+
+    pub fn add_property(&mut self, property: Property) {
+        self.properties.push(property);
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Read, Write};
+use echain::{ErrorKind, Result};
+use parser::{parse_file, parse_fragment, Fragment, ParsedSource};
+use dump::dump_nodes;
+use json::write_json;
+use query::Query;
+
+/// How a source argument can be given.
+const SOURCE_USAGE: &str = "<path> | - | --expr <code> | --item <code> | --stmt <code>";
+
+/// Where source text comes from: a file, stdin (`-`), or a snippet given on
+/// the command line.
+pub(crate) enum SourceArg {
+    Path(String),
+    Stdin,
+    Snippet(Fragment, String),
+}
+
+impl SourceArg {
+    /// The file name, or a placeholder like `<stdin>` or `<expr>`.
+    pub fn name(&self) -> &str {
+        match *self {
+            SourceArg::Path(ref path) => path,
+            SourceArg::Stdin => "<stdin>",
+            SourceArg::Snippet(fragment, _) => fragment.source_name(),
+        }
+    }
+
+    /// The text of stdin or of the snippet; files are read by the parser.
+    pub fn text(&self) -> Result<Option<String>> {
+        match *self {
+            SourceArg::Path(_) => Ok(None),
+            SourceArg::Stdin => {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text)?;
+                Ok(Some(text))
+            }
+            SourceArg::Snippet(_, ref code) => Ok(Some(code.clone())),
+        }
+    }
+
+    pub fn fragment(&self) -> Fragment {
+        match *self {
+            SourceArg::Snippet(fragment, _) => fragment,
+            _ => Fragment::Crate,
+        }
+    }
+}
+
+/// Splits arguments into sources; everything that isn't `-` or a snippet
+/// flag is a path.
+pub(crate) fn source_args(args: &[String]) -> Result<Vec<SourceArg>> {
+    let mut sources = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let fragment = match arg.as_str() {
+            "-" => {
+                sources.push(SourceArg::Stdin);
+                continue;
+            }
+            "--expr" => Fragment::Expr,
+            "--item" => Fragment::Item,
+            "--stmt" => Fragment::Stmt,
+            _ => {
+                sources.push(SourceArg::Path(arg.clone()));
+                continue;
+            }
+        };
+        let code = args.next()
+            .ok_or_else(|| ErrorKind::Usage(format!("{} needs the code to parse", arg)))?;
+        sources.push(SourceArg::Snippet(fragment, code.clone()));
+    }
+    Ok(sources)
+}
+
+/// Strips a leading `--expand`, which asks for the tree after macro expansion.
+fn expand_flag(args: &[String]) -> (bool, &[String]) {
+    match args.first() {
+        Some(arg) if arg == "--expand" => (true, &args[1..]),
+        _ => (false, args),
+    }
+}
+
+fn source_arg(args: &[String], usage: &str) -> Result<SourceArg> {
+    let mut sources = source_args(args)?;
+    if sources.len() != 1 {
+        bail!(ErrorKind::Usage(format!("usage: {}", usage)));
+    }
+    Ok(sources.remove(0))
+}
+
+/// Parses the source, reporting diagnostics to stderr. Partial trees of
+/// files with recoverable errors are still returned.
+fn parse_reporting(source: &SourceArg, expand: bool) -> Result<ParsedSource> {
+    let parsed = match source.text()? {
+        Some(text) => parse_fragment(source.name(), &text, source.fragment(), expand),
+        None => parse_file(source.name(), expand)?,
+    };
+    for diagnostic in &parsed.diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    Ok(parsed)
+}
+
+fn check_errors(name: &str, parsed: &ParsedSource) -> Result<()> {
+    if parsed.has_errors {
+        bail!(ErrorKind::ParseFailed(name.to_owned()));
+    }
+    Ok(())
+}
+
+/// `syntax_visualizer --dump [--expand] <source>`: prints the syntax tree to stdout.
+pub(crate) fn dump_main(args: &[String]) -> Result<()> {
+    let (expand, args) = expand_flag(args);
+    let source = source_arg(args, &format!("syntax_visualizer --dump [--expand] {}", SOURCE_USAGE))?;
+    let parsed = parse_reporting(&source, expand)?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    dump_nodes(&mut out, &parsed.nodes)?;
+    out.flush()?;
+    check_errors(source.name(), &parsed)
+}
+
+/// `syntax_visualizer --json [--expand] <source>`: prints the syntax tree as JSON to stdout.
+pub(crate) fn json_main(args: &[String]) -> Result<()> {
+    let (expand, args) = expand_flag(args);
+    let source = source_arg(args, &format!("syntax_visualizer --json [--expand] {}", SOURCE_USAGE))?;
+    let parsed = parse_reporting(&source, expand)?;
+
+    let stdout = io::stdout();
+    write_json(stdout.lock(), &parsed.nodes)?;
+    check_errors(source.name(), &parsed)
+}
+
+/// `syntax_visualizer --query [--expand] <query> <source>...`: prints one
+/// `file:line:col: Type Kind` line per node matching the query, so no output
+/// means nothing matched.
+pub(crate) fn query_main(args: &[String]) -> Result<()> {
+    let (expand, args) = expand_flag(args);
+    let usage = format!("usage: syntax_visualizer --query [--expand] <query> ({})...", SOURCE_USAGE);
+    let query = match args.first() {
+        Some(query) => Query::parse(query)?,
+        None => bail!(ErrorKind::Usage(usage)),
+    };
+    let sources = source_args(&args[1..])?;
+    if sources.is_empty() {
+        bail!(ErrorKind::Usage(usage));
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut failed = None;
+    for source in &sources {
+        let parsed = parse_reporting(source, expand)?;
+        for found in query.find_all(&parsed.nodes) {
+            let location = found.node.span
+                .and_then(|(lo, _)| parsed.positions.file_for_pos(lo).map(|file| (file, file.line_col(lo))));
+            match location {
+                Some((file, (line, col))) => write!(out, "{}:{}:{}:", file.name, line, col)?,
+                None => write!(out, "{}:", source.name())?,
+            }
+            write!(out, " {}", found.node.ty)?;
+            if !found.node.kind.is_empty() {
+                write!(out, " {}", found.node.kind)?;
+            }
+            writeln!(out)?;
+        }
+        if failed.is_none() && check_errors(source.name(), &parsed).is_err() {
+            failed = Some(source.name().to_owned());
+        }
+    }
+    out.flush()?;
+
+    match failed {
+        Some(name) => bail!(ErrorKind::ParseFailed(name)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use syntex_errors::{DiagnosticBuilder, FatalError};
+use syntex_errors::emitter::Emitter;
+
+/// A span referenced by a diagnostic, in raw codemap positions.
+#[derive(Clone, Debug)]
+pub(crate) struct DiagnosticSpan {
+    pub lo: u32,
+    pub hi: u32,
+    pub is_primary: bool,
+    pub label: Option<String>,
+}
+
+/// A parser diagnostic, detached from the `ParseSess` that produced it.
+#[derive(Clone, Debug)]
+pub(crate) struct Diagnostic {
+    pub level: String,
+    pub message: String,
+    pub spans: Vec<DiagnosticSpan>,
+    pub notes: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn primary_span(&self) -> Option<(u32, u32)> {
+        self.spans.iter()
+            .find(|s| s.is_primary)
+            .map(|s| (s.lo, s.hi))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.level, self.message)?;
+        for span in &self.spans {
+            write!(f, "\n  {} [{}..{})", if span.is_primary { "-->" } else { "   " }, span.lo, span.hi)?;
+            if let Some(ref label) = span.label {
+                write!(f, " {}", label)?;
+            }
+        }
+        for note in &self.notes {
+            write!(f, "\n  = {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// An `Emitter` that stores diagnostics instead of printing them,
+/// so they can be shown next to the (possibly partial) syntax tree.
+pub(crate) struct CollectingEmitter {
+    pub diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+}
+
+impl Emitter for CollectingEmitter {
+    fn emit(&mut self, db: &DiagnosticBuilder) {
+        let spans = db.span.span_labels().into_iter()
+            .map(|l| DiagnosticSpan {
+                lo: l.span.lo.0,
+                hi: l.span.hi.0,
+                is_primary: l.is_primary,
+                label: l.label,
+            })
+            .collect();
+
+        let notes = db.children.iter()
+            .map(|child| Diagnostic {
+                level: child.level.to_string(),
+                message: child.message(),
+                spans: child.span.primary_spans().iter()
+                    .map(|s| DiagnosticSpan { lo: s.lo.0, hi: s.hi.0, is_primary: true, label: None })
+                    .collect(),
+                notes: vec![],
+            })
+            .collect();
+
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            level: db.level.to_string(),
+            message: db.message(),
+            spans: spans,
+            notes: notes,
+        });
+    }
+}
+
+/// Keeps the panic hook from printing the `FatalError` panics syntex raises
+/// on errors it can't recover from; they have been emitted as diagnostics by
+/// then. Installed once at startup, as the hook is shared by all threads.
+pub(crate) fn silence_fatal_errors() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if !info.payload().is::<FatalError>() {
+            hook(info);
+        }
+    }));
+}
+
+/// Runs `f`, catching the `FatalError` panic syntex raises on errors it
+/// can't recover from, like an unterminated string. Other panics are
+/// passed on.
+pub(crate) fn catch_fatal<T, F: FnOnce() -> T>(f: F) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => Some(value),
+        Err(ref payload) if payload.is::<FatalError>() => None,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
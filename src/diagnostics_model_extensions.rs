@@ -0,0 +1,95 @@
+use gtk::prelude::*;
+use gtk::{TreeModel, TreeIter, TreeStore, TreeModelExt, TreeStoreExtManual};
+use diagnostics::Diagnostic;
+
+pub(crate) enum DiagnosticsColumns {
+    Level = 0,
+    Message = 1,
+    HasSpan = 2,
+    Lo = 3,
+    Hi = 4,
+}
+
+pub(crate) trait DiagnosticsModelExt {
+    fn get_diagnostic_span(&self, iter: &TreeIter) -> Option<(u32, u32)>;
+}
+
+macro_rules! get_diagnostics_model_value {
+    ($model:expr, $iter:expr, $col:ident, $type:ty) => {
+        $model.get_value($iter, DiagnosticsColumns::$col as i32)
+                    .get::<$type>().expect("Could not get value from TreeStore")
+    }
+}
+
+impl<O: IsA<TreeModel> + TreeModelExt> DiagnosticsModelExt for O {
+    fn get_diagnostic_span(&self, iter: &TreeIter) -> Option<(u32, u32)> {
+        let has_span = get_diagnostics_model_value!(self, iter, HasSpan, bool);
+        if !has_span {
+            None
+        }
+        else {
+            let lo = get_diagnostics_model_value!(self, iter, Lo, u32);
+            let hi = get_diagnostics_model_value!(self, iter, Hi, u32);
+            Some((lo, hi))
+        }
+    }
+}
+
+pub(crate) trait DiagnosticsStoreExt {
+    fn new_diagnostics_store() -> TreeStore;
+    fn insert_diagnostic(&self, iter: Option<&TreeIter>, diagnostic: &Diagnostic) -> TreeIter;
+    fn insert_diagnostic_row(&self, iter: Option<&TreeIter>, level: &str, message: &str, span: Option<(u32, u32)>) -> TreeIter;
+}
+
+impl<O: IsA<TreeStore> + TreeStoreExtManual> DiagnosticsStoreExt for O {
+    fn new_diagnostics_store() -> TreeStore {
+        TreeStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            bool::static_type(),
+            u32::static_type(),
+            u32::static_type()
+        ])
+    }
+
+    /// Inserts the diagnostic with its secondary spans and notes as child rows.
+    fn insert_diagnostic(&self, iter: Option<&TreeIter>, diagnostic: &Diagnostic) -> TreeIter {
+        let diagnostic_iter = self.insert_diagnostic_row(iter, &diagnostic.level, &diagnostic.message,
+                                                         diagnostic.primary_span());
+
+        for span in diagnostic.spans.iter().filter(|s| !s.is_primary) {
+            let label = span.label.as_ref().map(|l| l.as_str()).unwrap_or("");
+            self.insert_diagnostic_row(Some(&diagnostic_iter), "span", label, Some((span.lo, span.hi)));
+        }
+
+        for note in &diagnostic.notes {
+            self.insert_diagnostic(Some(&diagnostic_iter), note);
+        }
+
+        diagnostic_iter
+    }
+
+    fn insert_diagnostic_row(&self, iter: Option<&TreeIter>, level: &str, message: &str, span: Option<(u32, u32)>) -> TreeIter {
+        let mut cols: Vec<u32> = vec![
+            DiagnosticsColumns::Level as u32,
+            DiagnosticsColumns::Message as u32,
+            DiagnosticsColumns::HasSpan as u32
+        ];
+        let mut vals: Vec<&ToValue> = vec![
+            &level,
+            &message,
+            &false
+        ];
+
+        if let Some((ref lo, ref hi)) = span {
+            cols.push(DiagnosticsColumns::Lo as u32);
+            cols.push(DiagnosticsColumns::Hi as u32);
+
+            vals[DiagnosticsColumns::HasSpan as usize] = &true;
+            vals.push(lo);
+            vals.push(hi);
+        }
+
+        self.insert_with_values(iter, None, &cols, &vals)
+    }
+}
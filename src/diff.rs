@@ -0,0 +1,349 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use ast_node::{AstNode, Property};
+
+/// Properties that differ between two parses of identical code.
+const IGNORED_PROPERTIES: &[&str] = &["NodeId", "SpanOrigin"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DiffStatus {
+    Unchanged,
+    /// Matched, but its own properties differ; children have their own status.
+    Changed,
+    Added,
+    Removed,
+    /// An identical subtree exists elsewhere on the other side.
+    Moved,
+}
+
+impl fmt::Display for DiffStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiffStatus::Unchanged => Ok(()),
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+/// The diff status of one node, shaped like the tree it describes.
+#[derive(Clone, Debug)]
+pub(crate) struct DiffNode {
+    pub status: DiffStatus,
+    /// Child indices of the matching node on the other side.
+    pub counterpart: Option<Vec<usize>>,
+    pub children: Vec<DiffNode>,
+}
+
+impl DiffNode {
+    fn new(node: &AstNode, status: DiffStatus) -> DiffNode {
+        DiffNode {
+            status: status,
+            counterpart: None,
+            children: node.children.iter().map(|child| DiffNode::new(child, status)).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DiffSummary {
+    pub changed: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub moved: usize,
+}
+
+impl fmt::Display for DiffSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.changed + self.added + self.removed + self.moved == 0 {
+            return write!(f, "No structural changes");
+        }
+        write!(f, "{} changed, {} added, {} removed, {} moved",
+               self.changed, self.added, self.removed, self.moved)
+    }
+}
+
+/// Structural diff of two forests; `old` and `new` mirror the input trees.
+/// Added, removed and moved subtrees are counted once, at their root.
+pub(crate) struct TreeDiff {
+    pub old: Vec<DiffNode>,
+    pub new: Vec<DiffNode>,
+    pub summary: DiffSummary,
+}
+
+fn hash_properties<H: Hasher>(properties: &[Property], hasher: &mut H) {
+    for property in properties {
+        if IGNORED_PROPERTIES.contains(&property.name.as_str()) {
+            continue;
+        }
+        property.name.hash(hasher);
+        property.value.hash(hasher);
+        hash_properties(&property.children, hasher);
+    }
+}
+
+/// A node's own content, without its children.
+fn label_hash(node: &AstNode) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    node.ty.hash(&mut hasher);
+    node.kind.hash(&mut hasher);
+    hash_properties(&node.properties, &mut hasher);
+    hasher.finish()
+}
+
+/// Hashes of every subtree, in the shape of the tree.
+struct Hashes {
+    label: u64,
+    subtree: u64,
+    children: Vec<Hashes>,
+}
+
+impl Hashes {
+    fn new(node: &AstNode) -> Hashes {
+        let children: Vec<Hashes> = node.children.iter().map(Hashes::new).collect();
+        let label = label_hash(node);
+        let mut hasher = DefaultHasher::new();
+        label.hash(&mut hasher);
+        for child in &children {
+            child.subtree.hash(&mut hasher);
+        }
+        Hashes { label: label, subtree: hasher.finish(), children: children }
+    }
+}
+
+/// Pairs up children with the same Type and Kind, keeping their order and
+/// preferring identical subtrees. Returns `(old index, new index)` pairs.
+fn align(old: &[AstNode], old_hashes: &[Hashes], new: &[AstNode], new_hashes: &[Hashes]) -> Vec<(usize, usize)> {
+    let score = |i: usize, j: usize| -> u32 {
+        if old_hashes[i].subtree == new_hashes[j].subtree {
+            3
+        }
+        else if old[i].ty == new[j].ty && old[i].kind == new[j].kind {
+            if old_hashes[i].label == new_hashes[j].label { 2 } else { 1 }
+        }
+        else {
+            0
+        }
+    };
+
+    // Weighted longest common subsequence.
+    let (n, m) = (old.len(), new.len());
+    let mut table = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            let pair = match score(i, j) {
+                0 => 0,
+                s => s + table[i + 1][j + 1],
+            };
+            table[i][j] = pair.max(table[i + 1][j]).max(table[i][j + 1]);
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        let s = score(i, j);
+        if s > 0 && table[i][j] == s + table[i + 1][j + 1] {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        }
+        else if table[i][j] == table[i + 1][j] {
+            i += 1;
+        }
+        else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+/// Root of an added or removed subtree, kept for move detection.
+struct Unmatched {
+    path: Vec<usize>,
+    hash: u64,
+}
+
+struct Differ {
+    summary: DiffSummary,
+    removed: Vec<Unmatched>,
+    added: Vec<Unmatched>,
+}
+
+impl Differ {
+    fn diff_children(&mut self, old: &[AstNode], old_hashes: &[Hashes], old_path: &[usize],
+                     new: &[AstNode], new_hashes: &[Hashes], new_path: &[usize])
+                     -> (Vec<DiffNode>, Vec<DiffNode>) {
+        let mut old_diff: Vec<DiffNode> = old.iter().map(|node| DiffNode::new(node, DiffStatus::Removed)).collect();
+        let mut new_diff: Vec<DiffNode> = new.iter().map(|node| DiffNode::new(node, DiffStatus::Added)).collect();
+        let pairs = align(old, old_hashes, new, new_hashes);
+
+        for &(i, j) in &pairs {
+            let mut old_child_path = old_path.to_vec();
+            old_child_path.push(i);
+            let mut new_child_path = new_path.to_vec();
+            new_child_path.push(j);
+
+            let status = if old_hashes[i].label == new_hashes[j].label {
+                DiffStatus::Unchanged
+            }
+            else {
+                self.summary.changed += 1;
+                DiffStatus::Changed
+            };
+            let (old_children, new_children) = self.diff_children(
+                &old[i].children, &old_hashes[i].children, &old_child_path,
+                &new[j].children, &new_hashes[j].children, &new_child_path);
+
+            old_diff[i] = DiffNode { status: status, counterpart: Some(new_child_path.clone()), children: old_children };
+            new_diff[j] = DiffNode { status: status, counterpart: Some(old_child_path), children: new_children };
+        }
+
+        for i in (0..old.len()).filter(|i| !pairs.iter().any(|&(pi, _)| pi == *i)) {
+            let mut path = old_path.to_vec();
+            path.push(i);
+            self.removed.push(Unmatched { path: path, hash: old_hashes[i].subtree });
+        }
+        for j in (0..new.len()).filter(|j| !pairs.iter().any(|&(_, pj)| pj == *j)) {
+            let mut path = new_path.to_vec();
+            path.push(j);
+            self.added.push(Unmatched { path: path, hash: new_hashes[j].subtree });
+        }
+        (old_diff, new_diff)
+    }
+}
+
+/// The node at `path`, as given by child indices.
+pub(crate) fn diff_node_at<'a>(nodes: &'a [DiffNode], path: &[usize]) -> Option<&'a DiffNode> {
+    let (first, rest) = path.split_first()?;
+    let node = nodes.get(*first)?;
+    if rest.is_empty() { Some(node) } else { diff_node_at(&node.children, rest) }
+}
+
+fn node_at_mut<'a>(nodes: &'a mut [DiffNode], path: &[usize]) -> &'a mut DiffNode {
+    let (first, rest) = path.split_first().expect("Empty diff path");
+    let node = &mut nodes[*first];
+    if rest.is_empty() { node } else { node_at_mut(&mut node.children, rest) }
+}
+
+fn set_status(node: &mut DiffNode, status: DiffStatus) {
+    node.status = status;
+    for child in &mut node.children {
+        set_status(child, status);
+    }
+}
+
+/// Matches up the two trees and marks what was added, removed, moved or
+/// changed between them.
+pub(crate) fn diff_trees(old: &[AstNode], new: &[AstNode]) -> TreeDiff {
+    let old_hashes: Vec<Hashes> = old.iter().map(Hashes::new).collect();
+    let new_hashes: Vec<Hashes> = new.iter().map(Hashes::new).collect();
+
+    let mut differ = Differ { summary: DiffSummary::default(), removed: vec![], added: vec![] };
+    let (mut old_diff, mut new_diff) = differ.diff_children(old, &old_hashes, &[], new, &new_hashes, &[]);
+
+    // A removed subtree that reappears unchanged elsewhere was moved.
+    let mut added_by_hash: HashMap<u64, Vec<Vec<usize>>> = HashMap::new();
+    for added in differ.added.drain(..).rev() {
+        added_by_hash.entry(added.hash).or_insert_with(Vec::new).push(added.path);
+    }
+    for removed in differ.removed.drain(..) {
+        let new_path = added_by_hash.get_mut(&removed.hash).and_then(|paths| paths.pop());
+        match new_path {
+            Some(new_path) => {
+                differ.summary.moved += 1;
+                let old_node = node_at_mut(&mut old_diff, &removed.path);
+                set_status(old_node, DiffStatus::Moved);
+                old_node.counterpart = Some(new_path.clone());
+                let new_node = node_at_mut(&mut new_diff, &new_path);
+                set_status(new_node, DiffStatus::Moved);
+                new_node.counterpart = Some(removed.path);
+            }
+            None => differ.summary.removed += 1,
+        }
+    }
+    differ.summary.added += added_by_hash.values().map(|paths| paths.len()).sum::<usize>();
+
+    TreeDiff { old: old_diff, new: new_diff, summary: differ.summary }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast_node::{AstNode, Property};
+    use super::{diff_trees, DiffNode, DiffStatus};
+
+    /// Spans play no part in diffing, so the nodes here have none.
+    fn node(ty: &str, kind: &str, children: Vec<AstNode>) -> AstNode {
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            children: children,
+            ..AstNode::default()
+        }
+    }
+
+    fn with_property(mut node: AstNode, name: &str, value: &str) -> AstNode {
+        node.add_property(Property::new(name, value));
+        node
+    }
+
+    fn statuses(nodes: &[DiffNode]) -> Vec<DiffStatus> {
+        nodes.iter().map(|node| node.status).collect()
+    }
+
+    #[test]
+    fn identical_trees_are_unchanged() {
+        let old = vec![node("Item", "Fn", vec![node("Block", "", vec![])])];
+        let new = vec![with_property(old[0].clone(), "NodeId", "42")];
+        let diff = diff_trees(&old, &new);
+        assert_eq!(diff.summary.to_string(), "No structural changes");
+        assert_eq!(statuses(&diff.old), vec![DiffStatus::Unchanged]);
+        assert_eq!(statuses(&diff.old[0].children), vec![DiffStatus::Unchanged]);
+        assert_eq!(diff.new[0].children[0].counterpart, Some(vec![0, 0]));
+    }
+
+    #[test]
+    fn aligns_around_inserted_and_removed_nodes() {
+        let a = node("Item", "Fn", vec![]);
+        let b = node("Item", "Struct", vec![]);
+        let c = node("Item", "Enum", vec![]);
+        let d = node("Item", "Trait", vec![]);
+        let old = vec![a.clone(), c.clone(), d];
+        let new = vec![a, b, c];
+        let diff = diff_trees(&old, &new);
+
+        assert_eq!(statuses(&diff.old), vec![DiffStatus::Unchanged, DiffStatus::Unchanged, DiffStatus::Removed]);
+        assert_eq!(statuses(&diff.new), vec![DiffStatus::Unchanged, DiffStatus::Added, DiffStatus::Unchanged]);
+        assert_eq!(diff.old[1].counterpart, Some(vec![2]));
+        assert_eq!(diff.new[1].counterpart, None);
+        assert_eq!((diff.summary.added, diff.summary.removed), (1, 1));
+    }
+
+    #[test]
+    fn marks_changed_properties() {
+        let old = vec![with_property(node("Item", "Fn", vec![node("Block", "", vec![])]), "Name", "a")];
+        let new = vec![with_property(node("Item", "Fn", vec![node("Block", "", vec![])]), "Name", "b")];
+        let diff = diff_trees(&old, &new);
+        assert_eq!(statuses(&diff.new), vec![DiffStatus::Changed]);
+        assert_eq!(statuses(&diff.new[0].children), vec![DiffStatus::Unchanged]);
+        assert_eq!(diff.summary.changed, 1);
+    }
+
+    #[test]
+    fn detects_moved_subtrees() {
+        let moved = node("Item", "Fn", vec![node("Block", "", vec![])]);
+        let b = node("Item", "Struct", vec![]);
+        let c = node("Item", "Enum", vec![]);
+        let old = vec![moved.clone(), b.clone(), c.clone()];
+        let new = vec![b, c, moved];
+        let diff = diff_trees(&old, &new);
+
+        assert_eq!(statuses(&diff.old), vec![DiffStatus::Moved, DiffStatus::Unchanged, DiffStatus::Unchanged]);
+        assert_eq!(statuses(&diff.new), vec![DiffStatus::Unchanged, DiffStatus::Unchanged, DiffStatus::Moved]);
+        assert_eq!(statuses(&diff.new[2].children), vec![DiffStatus::Moved]);
+        assert_eq!(diff.old[0].counterpart, Some(vec![2]));
+        assert_eq!(diff.new[2].counterpart, Some(vec![0]));
+        assert_eq!((diff.summary.moved, diff.summary.added, diff.summary.removed), (1, 0, 0));
+    }
+}
@@ -0,0 +1,285 @@
+use std::cell::Cell;
+use std::rc::{Rc, Weak};
+use echain::{ErrorKind, Result};
+use gtk::prelude::*;
+use gtk::{Builder, Window, Label, TreeView, TreeViewExt, TreeViewColumn, CellRendererText, TreeStore, TreePath, TextTag};
+use sourceview::{Buffer, BufferExt, LanguageManager, LanguageManagerExt, View};
+
+use ast_node::AstNode;
+use ast_model_extensions::{self, AstModelExt, AstStoreExt, AstModelColumns};
+use diff::{self, DiffNode, DiffStatus, TreeDiff};
+use parser;
+use position::PositionMap;
+use tree_column_set_data_func_ext::TreeViewColumnSetCellDataFuncExt;
+
+/// Background colors of the diff statuses, in both the trees and the source.
+const STATUS_COLORS: &[(DiffStatus, &str)] = &[
+    (DiffStatus::Changed, "#fff3c4"),
+    (DiffStatus::Added, "#d4f4d2"),
+    (DiffStatus::Removed, "#ffd7d5"),
+    (DiffStatus::Moved, "#d8e6ff"),
+];
+
+/// Text to compare: a file on disk, or a document's buffer.
+pub(crate) struct DiffSource {
+    pub name: String,
+    pub text: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    Old,
+    New,
+}
+
+impl Side {
+    fn nodes(self, diff: &TreeDiff) -> &[DiffNode] {
+        match self {
+            Side::Old => &diff.old,
+            Side::New => &diff.new,
+        }
+    }
+}
+
+fn status_color(status: DiffStatus) -> Option<&'static str> {
+    STATUS_COLORS.iter()
+        .find(|&&(s, _)| s == status)
+        .map(|&(_, color)| color)
+}
+
+fn tag_name(status: DiffStatus) -> String {
+    status.to_string().to_lowercase()
+}
+
+fn path_indices(path: &TreePath) -> Vec<usize> {
+    path.get_indices().iter().map(|&index| index as usize).collect()
+}
+
+fn add_diff_columns(tree: &TreeView, diff: Rc<TreeDiff>, side: Side) {
+    column!(tree, type_col, type_cell, CellRendererText, "Type", true);
+    type_col.add_attribute(&type_cell, "text", AstModelColumns::Type as i32);
+    column!(tree, kind_col, kind_cell, CellRendererText, "Kind", true);
+    kind_col.add_attribute(&kind_cell, "text", AstModelColumns::Kind as i32);
+    column!(tree, status_col, status_cell, CellRendererText, "Diff", false);
+
+    for &(col, cell) in &[(&type_col, &type_cell), (&kind_col, &kind_cell), (&status_col, &status_cell)] {
+        let diff = diff.clone();
+        let is_status = col == &status_col;
+        col.set_cell_data_func(cell, move |_column, cell, model, iter| {
+            let text_cell = cell.clone().downcast::<CellRendererText>()
+                .expect("Couldn't downcast to CellRendererText");
+
+            let path = model.get_path(iter).expect("Could not get tree path");
+            let status = diff::diff_node_at(side.nodes(&diff), &path_indices(&path))
+                .map(|node| node.status)
+                .unwrap_or(DiffStatus::Unchanged);
+            text_cell.set_property_cell_background(status_color(status));
+            text_cell.set_property_cell_background_set(status != DiffStatus::Unchanged);
+            if is_status {
+                text_cell.set_property_text(Some(&status.to_string()));
+            }
+        });
+    }
+}
+
+/// Tags what changed in the source: whole subtrees that were added, removed
+/// or moved, and for changed nodes only the text not covered by children.
+fn tag_changes(buffer: &Buffer, positions: &PositionMap, nodes: &[AstNode], diff: &[DiffNode]) {
+    for (node, diff_node) in nodes.iter().zip(diff) {
+        match diff_node.status {
+            DiffStatus::Unchanged => {}
+            DiffStatus::Changed => {
+                if let Some((lo, hi)) = node.span {
+                    let mut children: Vec<(u32, u32)> = node.children.iter().filter_map(|child| child.span).collect();
+                    children.sort();
+                    let mut start = lo;
+                    for (child_lo, child_hi) in children {
+                        if child_lo > start {
+                            tag_span(buffer, positions, "changed", (start, child_lo));
+                        }
+                        start = start.max(child_hi);
+                    }
+                    if hi > start {
+                        tag_span(buffer, positions, "changed", (start, hi));
+                    }
+                }
+            }
+            status => {
+                if let Some(span) = node.span {
+                    tag_span(buffer, positions, &tag_name(status), span);
+                }
+                continue;
+            }
+        }
+        tag_changes(buffer, positions, &node.children, &diff_node.children);
+    }
+}
+
+fn tag_span(buffer: &Buffer, positions: &PositionMap, tag: &str, span: (u32, u32)) {
+    if let Some((lo, hi)) = positions.file_char_span(0, span) {
+        let lo_iter = buffer.get_iter_at_offset(lo as i32);
+        let hi_iter = buffer.get_iter_at_offset(hi as i32);
+        buffer.apply_tag_by_name(tag, &lo_iter, &hi_iter);
+    }
+}
+
+/// The widgets and parse results of one side of the diff.
+struct DiffPane {
+    source_view: View,
+    buffer: Buffer,
+    tree_view: TreeView,
+    positions: PositionMap,
+}
+
+impl DiffPane {
+    fn new(builder: &Builder, side: &str, source: &DiffSource, expand: bool) -> Result<(DiffPane, Vec<AstNode>)> {
+        let label: Label = builder.get_object(&format!("{}_label", side))
+            .ok_or(ErrorKind::WidgetNotFound("diff label"))?;
+        let source_view: View = builder.get_object(&format!("{}_source_view", side))
+            .ok_or(ErrorKind::WidgetNotFound("diff source view"))?;
+        let tree_view: TreeView = builder.get_object(&format!("{}_tree_view", side))
+            .ok_or(ErrorKind::WidgetNotFound("diff tree view"))?;
+
+        let buffer: Buffer = source_view.get_buffer()
+            .ok_or(ErrorKind::WidgetNotFound("Buffer"))?
+            .downcast::<Buffer>()
+            .map_err(|_| ErrorKind::DowncastFailed("TextBuffer", "Buffer"))?;
+        let tag_table = buffer.get_tag_table().ok_or(ErrorKind::WidgetNotFound("TagTable"))?;
+        for &(status, color) in STATUS_COLORS {
+            let tag = TextTag::new(tag_name(status).as_str());
+            tag.set_property_background(Some(color));
+            tag_table.add(&tag);
+        }
+        let tag = TextTag::new("highlighted");
+        tag.set_property_background(Some("#dcebff"));
+        tag_table.add(&tag);
+
+        if let Some(lang) = LanguageManager::new().get_language("rust") {
+            buffer.set_language(Some(&lang));
+        }
+        buffer.set_text(&source.text);
+        label.set_text(&source.name);
+
+        let parsed = parser::parse_source(&source.name, &source.text, expand);
+        let pane = DiffPane {
+            source_view: source_view,
+            buffer: buffer,
+            tree_view: tree_view,
+            positions: parsed.positions,
+        };
+        Ok((pane, parsed.nodes))
+    }
+
+    fn highlight(&self, span: Option<(u32, u32)>) {
+        let (start_iter, end_iter) = self.buffer.get_bounds();
+        self.buffer.remove_tag_by_name("highlighted", &start_iter, &end_iter);
+
+        if let Some((lo, hi)) = span.and_then(|span| self.positions.file_char_span(0, span)) {
+            let mut lo_iter = self.buffer.get_iter_at_offset(lo as i32);
+            let hi_iter = self.buffer.get_iter_at_offset(hi as i32);
+            self.buffer.apply_tag_by_name("highlighted", &lo_iter, &hi_iter);
+            self.source_view.scroll_to_iter(&mut lo_iter, 0.1, false, 0.0, 0.0);
+        }
+    }
+}
+
+/// Two versions of a file side by side, with their syntax trees matched up
+/// and the differences colored.
+pub(crate) struct DiffWindow {
+    pub window: Window,
+    old: DiffPane,
+    new: DiffPane,
+    diff: Rc<TreeDiff>,
+    /// Set while selecting the counterpart of the node selected on the
+    /// other side.
+    syncing: Cell<bool>,
+}
+
+impl DiffWindow {
+    pub fn new(old: &DiffSource, new: &DiffSource, expand: bool) -> Result<Rc<DiffWindow>> {
+        let builder = Builder::new_from_string(include_str!("diff.glade"));
+        get_widget!(builder, diff_window, Window);
+        get_widget!(builder, diff_summary, Label);
+
+        let (old_pane, old_nodes) = DiffPane::new(&builder, "old", old, expand)?;
+        let (new_pane, new_nodes) = DiffPane::new(&builder, "new", new, expand)?;
+        let diff = Rc::new(diff::diff_trees(&old_nodes, &new_nodes));
+        diff_summary.set_text(&diff.summary.to_string());
+
+        for &(pane, nodes, side) in &[(&old_pane, &old_nodes, Side::Old), (&new_pane, &new_nodes, Side::New)] {
+            let store = TreeStore::new_ast_store();
+            store.insert_nodes(None, nodes);
+            add_diff_columns(&pane.tree_view, diff.clone(), side);
+            pane.tree_view.set_model(Some(&store));
+            tag_changes(&pane.buffer, &pane.positions, nodes, side.nodes(&diff));
+        }
+
+        let window = Rc::new(DiffWindow {
+            window: diff_window,
+            old: old_pane,
+            new: new_pane,
+            diff: diff,
+            syncing: Cell::new(false),
+        });
+        DiffWindow::connect_signals(&window);
+        Ok(window)
+    }
+
+    fn pane(&self, side: Side) -> &DiffPane {
+        match side {
+            Side::Old => &self.old,
+            Side::New => &self.new,
+        }
+    }
+
+    fn connect_signals(window: &Rc<DiffWindow>) {
+        for &side in &[Side::Old, Side::New] {
+            let weak: Weak<DiffWindow> = Rc::downgrade(window);
+            window.pane(side).tree_view.get_selection().connect_changed(move |selection| {
+                if let Some(window) = weak.upgrade() {
+                    if let Some((model, iter)) = selection.get_selected() {
+                        let path = model.get_path(&iter).expect("Could not get tree path");
+                        window.node_selected(side, &path_indices(&path), model.get_span(&iter));
+                    }
+                }
+            });
+        }
+
+        // The handler, and with it the window, is dropped once it's destroyed.
+        let window_clone = window.clone();
+        window.window.connect_destroy(move |_| {
+            let _ = &window_clone;
+        });
+    }
+
+    /// Highlights the node and selects its counterpart on the other side.
+    fn node_selected(&self, side: Side, path: &[usize], span: Option<(u32, u32)>) {
+        self.pane(side).highlight(span);
+        if self.syncing.get() {
+            return;
+        }
+
+        let other_side = match side {
+            Side::Old => Side::New,
+            Side::New => Side::Old,
+        };
+        let other = self.pane(other_side);
+        let counterpart = diff::diff_node_at(side.nodes(&self.diff), path)
+            .and_then(|node| node.counterpart.clone());
+        let counterpart = match counterpart {
+            Some(counterpart) => counterpart,
+            None => {
+                other.tree_view.get_selection().unselect_all();
+                other.highlight(None);
+                return;
+            }
+        };
+
+        let tree_path = ast_model_extensions::tree_path(&counterpart);
+        self.syncing.set(true);
+        other.tree_view.expand_to_path(&tree_path);
+        other.tree_view.get_selection().select_path(&tree_path);
+        other.tree_view.scroll_to_cell(&tree_path, None, false, 0.0, 0.0);
+        self.syncing.set(false);
+    }
+}
@@ -0,0 +1,1020 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::rc::{Rc, Weak};
+use echain::{ErrorKind, Result};
+use gio::{self, FileExt, FileMonitorExt, FileMonitorEvent};
+use glib;
+use gtk::prelude::*;
+use gtk::{Builder, Paned, Label, TreeView, TreeViewExt, TreeViewColumn, CellRendererText, TreeStore, ListStore, TreeModel, TreeIter, TextTag};
+use gtk::{self, Button, Entry, SearchEntry, TreeModelFilter, TreePath, ReliefStyle};
+use sourceview::{Buffer, BufferExt, LanguageManager, LanguageManagerExt, View};
+
+use parser::{self, Fragment, ParsedSource};
+use ast_node::AstNode;
+use tree_column_set_data_func_ext::TreeViewColumnSetCellDataFuncExt;
+use ast_model_extensions::{AstModelExt, AstStoreExt, AstModelColumns, AstPropertiesStoreExt, AstPropertiesColumns};
+use node_filter::NodeFilter;
+use query::Query;
+use query_model_extensions::{QueryResultsModelExt, QueryResultsStoreExt, QueryResultsColumns};
+use diagnostics::Diagnostic;
+use diagnostics_model_extensions::{DiagnosticsModelExt, DiagnosticsStoreExt, DiagnosticsColumns};
+use tokens::SourceToken;
+use tokens_model_extensions::{TokensModelExt, TokensStoreExt, TokensColumns};
+use outline::{self, ModuleOutline};
+use outline_model_extensions::{OutlineModelExt, OutlineStoreExt, OutlineColumns};
+use tree_view_state::TreeViewState;
+use span_index::SpanIndex;
+use position::{offset_after_edit, PositionMap, SpanFormat};
+
+/// How long the buffer has to stay unchanged before it is re-parsed.
+const REPARSE_DELAY_MS: u32 = 300;
+
+/// Text color of nodes generated by macro expansion.
+const EXPANDED_NODE_COLOR: &str = "#9a6700";
+
+/// Display settings shared by all open documents.
+pub(crate) struct ViewSettings {
+    pub span_format: Cell<SpanFormat>,
+    pub expand_macros: Cell<bool>,
+    pub editable: Cell<bool>,
+}
+
+impl ViewSettings {
+    pub fn new() -> ViewSettings {
+        ViewSettings {
+            span_format: Cell::new(SpanFormat::Bytes),
+            expand_macros: Cell::new(false),
+            editable: Cell::new(false),
+        }
+    }
+}
+
+fn add_ast_columns(tree: &TreeView, positions: Rc<RefCell<PositionMap>>, settings: Rc<ViewSettings>) {
+    column!(tree, type_col, type_cell, CellRendererText, "Type", true);
+    type_col.add_attribute(&type_cell, "text", AstModelColumns::Type as i32);
+    column!(tree, kind_col, kind_cell, CellRendererText, "Kind", true);
+    kind_col.add_attribute(&kind_cell, "text", AstModelColumns::Kind as i32);
+
+    for &(col, cell) in &[(&type_col, &type_cell), (&kind_col, &kind_cell)] {
+        cell.set_property_foreground(Some(EXPANDED_NODE_COLOR));
+        col.add_attribute(cell, "foreground-set", AstModelColumns::Expanded as i32);
+    }
+
+    column!(tree, span_col, span_cell, CellRendererText, "Span", false);
+    span_col.set_cell_data_func(&span_cell, move |_column, cell, model, iter| {
+        let text_cell = cell.clone().downcast::<CellRendererText>()
+            .expect("Couldn't downcast to CellRendererText");
+
+        if let Some(span) = model.get_span(iter) {
+            text_cell.set_property_text(Some(&positions.borrow().format_span(span, settings.span_format.get())));
+        }
+        else {
+            text_cell.set_property_text(Some(""));
+        }
+    });
+}
+
+fn add_properties_columns(list: &TreeView) {
+    column!(list, name_col, name_cell, CellRendererText, "Property", true);
+    name_col.add_attribute(&name_cell, "text", AstPropertiesColumns::Name as i32);
+    column!(list, value_col, value_cell, CellRendererText, "Value", true);
+    value_col.add_attribute(&value_cell, "text", AstPropertiesColumns::Value as i32);
+}
+
+fn add_diagnostics_columns(tree: &TreeView, positions: Rc<RefCell<PositionMap>>, settings: Rc<ViewSettings>) {
+    column!(tree, level_col, level_cell, CellRendererText, "Level", false);
+    level_col.add_attribute(&level_cell, "text", DiagnosticsColumns::Level as i32);
+    column!(tree, message_col, message_cell, CellRendererText, "Message", true);
+    message_col.add_attribute(&message_cell, "text", DiagnosticsColumns::Message as i32);
+
+    column!(tree, span_col, span_cell, CellRendererText, "Span", false);
+    span_col.set_cell_data_func(&span_cell, move |_column, cell, model, iter| {
+        let text_cell = cell.clone().downcast::<CellRendererText>()
+            .expect("Couldn't downcast to CellRendererText");
+
+        if let Some(span) = model.get_diagnostic_span(iter) {
+            text_cell.set_property_text(Some(&positions.borrow().format_span(span, settings.span_format.get())));
+        }
+        else {
+            text_cell.set_property_text(Some(""));
+        }
+    });
+}
+
+fn add_tokens_columns(list: &TreeView, positions: Rc<RefCell<PositionMap>>, settings: Rc<ViewSettings>) {
+    column!(list, kind_col, kind_cell, CellRendererText, "Token", false);
+    kind_col.add_attribute(&kind_cell, "text", TokensColumns::Kind as i32);
+    column!(list, text_col, text_cell, CellRendererText, "Text", true);
+    text_col.add_attribute(&text_cell, "text", TokensColumns::Text as i32);
+    column!(list, trivia_col, trivia_cell, CellRendererText, "Trivia", false);
+    trivia_col.add_attribute(&trivia_cell, "text", TokensColumns::Trivia as i32);
+
+    column!(list, span_col, span_cell, CellRendererText, "Span", false);
+    span_col.set_cell_data_func(&span_cell, move |_column, cell, model, iter| {
+        let text_cell = cell.clone().downcast::<CellRendererText>()
+            .expect("Couldn't downcast to CellRendererText");
+
+        let span = model.get_token_span(iter);
+        text_cell.set_property_text(Some(&positions.borrow().format_span(span, settings.span_format.get())));
+    });
+}
+
+/// The node's properties followed by its span, and the macro call site it
+/// was expanded from, in the current format.
+fn node_properties(model: &TreeModel, iter: &TreeIter, positions: &PositionMap, span_format: SpanFormat) -> TreeStore {
+    let props = model.get_properties_list(iter).copy_properties_store();
+    if let Some(span) = model.get_span(iter) {
+        props.insert_property(None, "Span", &positions.format_span(span, span_format));
+    }
+    if let Some(call_site) = model.get_call_site(iter) {
+        props.insert_property(None, "CallSite", &positions.format_span(call_site, span_format));
+    }
+    props
+}
+
+fn add_query_results_columns(list: &TreeView, positions: Rc<RefCell<PositionMap>>, settings: Rc<ViewSettings>) {
+    column!(list, type_col, type_cell, CellRendererText, "Type", false);
+    type_col.add_attribute(&type_cell, "text", QueryResultsColumns::Type as i32);
+    column!(list, kind_col, kind_cell, CellRendererText, "Kind", false);
+    kind_col.add_attribute(&kind_cell, "text", QueryResultsColumns::Kind as i32);
+    column!(list, name_col, name_cell, CellRendererText, "Name", true);
+    name_col.add_attribute(&name_cell, "text", QueryResultsColumns::Name as i32);
+
+    column!(list, span_col, span_cell, CellRendererText, "Span", false);
+    span_col.set_cell_data_func(&span_cell, move |_column, cell, model, iter| {
+        let text_cell = cell.clone().downcast::<CellRendererText>()
+            .expect("Couldn't downcast to CellRendererText");
+
+        if let Some(span) = model.get_result_span(iter) {
+            text_cell.set_property_text(Some(&positions.borrow().format_span(span, settings.span_format.get())));
+        }
+        else {
+            text_cell.set_property_text(Some(""));
+        }
+    });
+}
+
+fn add_outline_columns(tree: &TreeView) {
+    column!(tree, module_col, module_cell, CellRendererText, "Module", true);
+    module_col.add_attribute(&module_cell, "text", OutlineColumns::Module as i32);
+    column!(tree, file_col, file_cell, CellRendererText, "File", true);
+    file_col.add_attribute(&file_cell, "text", OutlineColumns::File as i32);
+}
+
+/// Highlights a span given in codemap positions, if it lies in the `file`-th
+/// file, which the buffer is expected to show.
+fn highlight_span(buffer: &Buffer, view: &View, positions: &PositionMap, file: usize, tag: &str, span: (u32, u32)) {
+    let (start_iter, end_iter) = buffer.get_bounds();
+    buffer.remove_tag_by_name(tag, &start_iter, &end_iter);
+
+    let (lo, hi) = match positions.file_char_span(file, span) {
+        Some(char_span) => char_span,
+        None => return,
+    };
+    let mut lo_iter = buffer.get_iter_at_offset(lo as i32);
+    let hi_iter = buffer.get_iter_at_offset(hi as i32);
+    buffer.apply_tag_by_name(tag, &lo_iter, &hi_iter);
+    view.scroll_to_iter(&mut lo_iter, 0.1, false, 0.0, 0.0);
+}
+
+fn open_file<T: AsRef<Path>>(path: T, buffer: &Buffer) -> Result<()> {
+    let file = File::open(&path)?;
+    let mut reader = BufReader::new(file);
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    buffer.set_text(&contents);
+
+    let lang_manager = LanguageManager::new();
+
+    if let Some(lang) = lang_manager.guess_language(path.as_ref().to_str(), None) {
+        buffer.set_language(Some(&lang));
+    }
+    Ok(())
+}
+
+fn new_syntax_tree_store(nodes: &[AstNode]) -> TreeStore {
+    let store = TreeStore::new_ast_store();
+    store.insert_nodes(None, nodes);
+    store
+}
+
+fn new_tokens_store(tokens: &[SourceToken]) -> ListStore {
+    let store = ListStore::new_tokens_store();
+    for token in tokens {
+        store.insert_token(token);
+    }
+    store
+}
+
+fn new_outline_store(modules: &[ModuleOutline]) -> TreeStore {
+    let store = TreeStore::new_outline_store();
+    for module in modules {
+        store.insert_module(None, module);
+    }
+    store
+}
+
+fn new_diagnostics_store(diagnostics: &[Diagnostic]) -> TreeStore {
+    let store = TreeStore::new_diagnostics_store();
+    for diagnostic in diagnostics {
+        store.insert_diagnostic(None, diagnostic);
+    }
+    store
+}
+
+
+/// Text of a breadcrumb, e.g. `Item(Fn)`.
+fn crumb_label(ty: &str, kind: &str) -> String {
+    if kind.is_empty() { ty.to_owned() } else { format!("{}({})", ty, kind) }
+}
+
+/// Selects, expands to and scrolls to the row.
+fn select_row(view: &TreeView, model: &TreeModel, iter: &TreeIter) {
+    let path = model.get_path(iter).expect("Could not get tree path");
+    view.expand_to_path(&path);
+    view.get_selection().select_iter(iter);
+    view.scroll_to_cell(&path, None, false, 0.0, 0.0);
+}
+
+/// Selects the row of a store path in a view that shows the store through a
+/// `TreeModelFilter`. Rows hidden by the filter can't be selected.
+fn select_store_path(view: &TreeView, path: &TreePath) {
+    let model = match view.get_model() {
+        Some(model) => model,
+        None => return,
+    };
+    let path = model.clone().downcast::<TreeModelFilter>().ok()
+        .and_then(|filter| filter.convert_child_path_to_path(path));
+    if let Some(iter) = path.and_then(|path| model.get_iter(&path)) {
+        select_row(view, &model, &iter);
+    }
+}
+
+/// A selected node, kept across a reload: the Type and Kind of it and its
+/// ancestors, and its byte offset in the `file`-th file.
+struct SelectedNode {
+    ancestry: Vec<(String, String)>,
+    file: usize,
+    offset: u32,
+}
+
+/// One open file: its source buffer, the views of its syntax tree and
+/// everything they were built from. Each notebook tab shows one document.
+pub(crate) struct Document {
+    pub panel: Paned,
+    /// Shows `title()`; the notebook puts it on the document's tab.
+    pub tab_label: Label,
+    source_view: View,
+    buffer: Buffer,
+    syntax_tree_view: TreeView,
+    node_properties_view: TreeView,
+    diagnostics_view: TreeView,
+    tokens_view: TreeView,
+    outline_view: TreeView,
+    source_file_label: Label,
+    /// The nodes around the cursor, outermost first.
+    breadcrumb_box: gtk::Box,
+    search_entry: SearchEntry,
+    search_status: Label,
+    search_previous_button: Button,
+    search_next_button: Button,
+    query_entry: Entry,
+    query_status: Label,
+    query_results_view: TreeView,
+    settings: Rc<ViewSettings>,
+    positions: Rc<RefCell<PositionMap>>,
+    nodes: RefCell<Vec<AstNode>>,
+    /// The unfiltered tree; the view shows it through a `TreeModelFilter`.
+    syntax_tree_store: RefCell<Option<TreeStore>>,
+    /// Finds the node under the cursor; built from `nodes` on every parse.
+    span_index: RefCell<SpanIndex>,
+    /// Paths in `syntax_tree_store` of the nodes matching the search.
+    search_hits: RefCell<Vec<TreePath>>,
+    current_hit: Cell<Option<usize>>,
+    path: RefCell<Option<PathBuf>>,
+    /// Name of text that didn't come from a file, like `<stdin>`.
+    name: RefCell<Option<String>>,
+    /// What the text is parsed as; snippets aren't whole crates.
+    fragment: Cell<Fragment>,
+    /// Scratchpads can always be edited, whatever `settings.editable` says.
+    scratch: Cell<bool>,
+    pending_reparse: RefCell<Option<glib::SourceId>>,
+    /// Watches `path` for changes made by other programs.
+    monitor: RefCell<Option<gio::FileMonitor>>,
+    pending_reload: RefCell<Option<glib::SourceId>>,
+    /// Index into `positions.files()` of the file in the buffer. Only the
+    /// root file (0) can be edited; other files are shown read-only.
+    shown_file: Cell<usize>,
+    /// Set while the buffer text is swapped, so the cursor moving to the
+    /// start doesn't change the selected node.
+    switching_file: Cell<bool>,
+}
+
+impl Document {
+    pub fn new(settings: Rc<ViewSettings>) -> Result<Rc<Document>> {
+        let builder = Builder::new_from_string(include_str!("document.glade"));
+
+        get_widget!(builder, document_panel, Paned);
+        get_widget!(builder, source_view, View);
+        get_widget!(builder, syntax_tree_view, TreeView);
+        get_widget!(builder, node_properties_view, TreeView);
+        get_widget!(builder, diagnostics_view, TreeView);
+        get_widget!(builder, tokens_view, TreeView);
+        get_widget!(builder, outline_view, TreeView);
+        get_widget!(builder, source_file_label, Label);
+        get_widget!(builder, breadcrumb_box, gtk::Box);
+        get_widget!(builder, search_entry, SearchEntry);
+        get_widget!(builder, search_status, Label);
+        get_widget!(builder, search_previous_button, Button);
+        get_widget!(builder, search_next_button, Button);
+        get_widget!(builder, query_entry, Entry);
+        get_widget!(builder, query_status, Label);
+        get_widget!(builder, query_results_view, TreeView);
+
+        let buffer: Buffer = source_view.get_buffer()
+            .ok_or(ErrorKind::WidgetNotFound("Buffer"))?
+            .downcast::<Buffer>()
+            .map_err(|_| ErrorKind::DowncastFailed("TextBuffer", "Buffer"))?;
+
+        let tag_table = buffer.get_tag_table().ok_or(ErrorKind::WidgetNotFound("TagTable"))?;
+        for &(name, color) in &[("highlighted", "#dcebff"), ("diagnostic", "#ffdcdc"), ("token", "#fff3c4")] {
+            let tag = TextTag::new(name);
+            tag.set_property_background(Some(color));
+            tag_table.add(&tag);
+        }
+
+        let positions: Rc<RefCell<PositionMap>> = Rc::new(RefCell::new(PositionMap::default()));
+        add_ast_columns(&syntax_tree_view, positions.clone(), settings.clone());
+        add_properties_columns(&node_properties_view);
+        add_diagnostics_columns(&diagnostics_view, positions.clone(), settings.clone());
+        add_tokens_columns(&tokens_view, positions.clone(), settings.clone());
+        add_outline_columns(&outline_view);
+        add_query_results_columns(&query_results_view, positions.clone(), settings.clone());
+        source_view.set_editable(settings.editable.get());
+
+        let document = Rc::new(Document {
+            panel: document_panel,
+            tab_label: Label::new(None),
+            source_view: source_view,
+            buffer: buffer,
+            syntax_tree_view: syntax_tree_view,
+            node_properties_view: node_properties_view,
+            diagnostics_view: diagnostics_view,
+            tokens_view: tokens_view,
+            outline_view: outline_view,
+            source_file_label: source_file_label,
+            breadcrumb_box: breadcrumb_box,
+            search_entry: search_entry,
+            search_status: search_status,
+            search_previous_button: search_previous_button,
+            search_next_button: search_next_button,
+            query_entry: query_entry,
+            query_status: query_status,
+            query_results_view: query_results_view,
+            settings: settings,
+            positions: positions,
+            nodes: RefCell::new(vec![]),
+            syntax_tree_store: RefCell::new(None),
+            span_index: RefCell::new(SpanIndex::default()),
+            search_hits: RefCell::new(vec![]),
+            current_hit: Cell::new(None),
+            path: RefCell::new(None),
+            name: RefCell::new(None),
+            fragment: Cell::new(Fragment::Crate),
+            scratch: Cell::new(false),
+            pending_reparse: RefCell::new(None),
+            monitor: RefCell::new(None),
+            pending_reload: RefCell::new(None),
+            shown_file: Cell::new(0),
+            switching_file: Cell::new(false),
+        });
+        Document::connect_signals(&document);
+        Ok(document)
+    }
+
+    /// Handlers hold weak references, so closing the tab frees the document.
+    fn connect_signals(document: &Rc<Document>) {
+        let weak = Rc::downgrade(document);
+        document.buffer.connect_changed(move |_| {
+            if let Some(document) = weak.upgrade() {
+                Document::schedule_reparse(&document);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.diagnostics_view.get_selection().connect_changed(move |selection| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            document.clear_highlight("diagnostic");
+
+            if let Some((model, iter)) = selection.get_selected() {
+                if let Some(span) = model.get_diagnostic_span(&iter) {
+                    document.highlight("diagnostic", span);
+                }
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.tokens_view.get_selection().connect_changed(move |selection| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            document.clear_highlight("token");
+
+            if let Some((model, iter)) = selection.get_selected() {
+                let span = model.get_token_span(&iter);
+                if let Some(tree_model) = document.syntax_tree_view.get_model() {
+                    if let Some(node_iter) = tree_model.find_node_by_span(span) {
+                        document.select_node(&tree_model, &node_iter);
+                    }
+                }
+                document.highlight("token", span);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.syntax_tree_view.get_selection().connect_changed(move |tree_selection| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            document.clear_highlight("highlighted");
+
+            if let Some((model, iter)) = tree_selection.get_selected() {
+                let props = node_properties(&model, &iter, &document.positions.borrow(),
+                                            document.settings.span_format.get());
+                document.node_properties_view.set_model(Some(&props));
+
+                // Generated code has no text of its own in the buffer; show where it was invoked.
+                if let Some(span) = model.get_call_site(&iter).or_else(|| model.get_span(&iter)) {
+                    document.highlight("highlighted", span);
+                }
+            }
+            else {
+                document.node_properties_view.set_model(None::<&TreeModel>);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.search_entry.connect_search_changed(move |_| {
+            if let Some(document) = weak.upgrade() {
+                if document.apply_search() {
+                    document.syntax_tree_view.expand_all();
+                }
+                document.show_hit(true);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.search_entry.connect_activate(move |_| {
+            if let Some(document) = weak.upgrade() {
+                document.show_hit(true);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.search_next_button.connect_clicked(move |_| {
+            if let Some(document) = weak.upgrade() {
+                document.show_hit(true);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.search_previous_button.connect_clicked(move |_| {
+            if let Some(document) = weak.upgrade() {
+                document.show_hit(false);
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.query_entry.connect_activate(move |_| {
+            if let Some(document) = weak.upgrade() {
+                document.run_query();
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.query_results_view.get_selection().connect_changed(move |selection| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            if let Some((model, iter)) = selection.get_selected() {
+                document.select_store_path(&model.get_result_path(&iter));
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.outline_view.get_selection().connect_changed(move |selection| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            if let Some((model, iter)) = selection.get_selected() {
+                let span = model.get_module_span(&iter);
+                if let Some(tree_model) = document.syntax_tree_view.get_model() {
+                    if let Some(node_iter) = tree_model.find_node_by_span(span) {
+                        document.select_node(&tree_model, &node_iter);
+                    }
+                }
+            }
+        });
+
+        let weak = Rc::downgrade(document);
+        document.buffer.connect_property_cursor_position_notify(move |buffer| {
+            let document = match weak.upgrade() {
+                Some(document) => document,
+                None => return,
+            };
+            if document.switching_file.get() {
+                return;
+            }
+            let store = match *document.syntax_tree_store.borrow() {
+                Some(ref store) => store.clone(),
+                None => return,
+            };
+            let chain = document.nodes_at_cursor(&store, buffer.get_property_cursor_position());
+            if let Some(path) = chain.last().and_then(|iter| store.get_path(iter)) {
+                document.select_store_path(&path);
+            }
+            document.show_breadcrumbs(&store, &chain);
+        });
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.path.borrow().clone()
+    }
+
+    /// Name used as the file name when parsing the buffer.
+    pub fn source_name(&self) -> String {
+        match *self.path.borrow() {
+            Some(ref path) => path.to_string_lossy().into_owned(),
+            None => self.name.borrow().clone().unwrap_or_else(|| "<buffer>".to_owned()),
+        }
+    }
+
+    /// Short name for the tab label.
+    pub fn title(&self) -> String {
+        self.path.borrow().as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .or_else(|| self.name.borrow().clone())
+            .unwrap_or_else(|| "Untitled".to_owned())
+    }
+
+    pub fn nodes(&self) -> Ref<Vec<AstNode>> {
+        self.nodes.borrow()
+    }
+
+    /// Reads `path` into the buffer and shows its syntax tree.
+    pub fn load(&self, path: &Path) -> Result<()> {
+        self.shown_file.set(0);
+        self.fragment.set(Fragment::Crate);
+        self.update_editable();
+        open_file(path, &self.buffer)?;
+        self.buffer.set_modified(false);
+        *self.path.borrow_mut() = Some(path.to_owned());
+        self.update_title();
+        self.show_parsed(parser::parse_file(path, self.settings.expand_macros.get())?);
+        Ok(())
+    }
+
+    /// Shows text that isn't backed by a file, e.g. stdin or a snippet.
+    pub fn load_text(&self, name: &str, text: &str, fragment: Fragment) {
+        self.shown_file.set(0);
+        self.update_editable();
+        *self.name.borrow_mut() = Some(name.to_owned());
+        self.fragment.set(fragment);
+        self.update_title();
+        if let Some(lang) = LanguageManager::new().get_language("rust") {
+            self.buffer.set_language(Some(&lang));
+        }
+        self.buffer.set_text(text);
+        self.buffer.set_modified(false);
+        self.show_parsed(parser::parse_fragment(name, text, fragment, self.settings.expand_macros.get()));
+    }
+
+    /// Starts a scratchpad with `text`, to type Rust into and watch it
+    /// being parsed.
+    pub fn load_scratch(&self, text: &str) {
+        self.scratch.set(true);
+        self.load_text("Scratchpad", text, Fragment::Crate);
+    }
+
+    /// Writes the root file's text to `path`, which the document shows from
+    /// then on.
+    pub fn save_as(document: &Rc<Document>, path: &Path) -> Result<()> {
+        let text = document.text().unwrap_or_default();
+        File::create(path)?.write_all(text.as_bytes())?;
+        *document.path.borrow_mut() = Some(path.to_owned());
+        document.buffer.set_modified(false);
+        document.update_title();
+        Document::watch_file(document);
+        Ok(())
+    }
+
+    pub fn reload(&self) -> Result<()> {
+        match self.path() {
+            Some(path) => self.load(&path),
+            None => Ok(()),
+        }
+    }
+
+    /// The root file's text, including edits that haven't been parsed yet.
+    pub fn text(&self) -> Option<String> {
+        match self.shown_file.get() {
+            0 => {
+                let (start_iter, end_iter) = self.buffer.get_bounds();
+                Some(self.buffer.get_text(&start_iter, &end_iter, true).unwrap_or_default())
+            }
+            _ => self.positions.borrow().root().and_then(|root| root.src.as_ref().map(|src| (**src).clone())),
+        }
+    }
+
+    /// Re-parses the buffer contents, e.g. after an edit or when switching
+    /// between the written and the macro-expanded tree.
+    pub fn reparse(&self) {
+        let text = match self.text() {
+            Some(text) => text,
+            None => return,
+        };
+        self.show_parsed(parser::parse_fragment(&self.source_name(), &text, self.fragment.get(),
+                                                self.settings.expand_macros.get()));
+    }
+
+    /// Puts the `file`-th file of the crate into the buffer.
+    fn show_file(&self, file: usize) {
+        if file == self.shown_file.get() {
+            return;
+        }
+        let (name, src) = match self.positions.borrow().files().get(file) {
+            Some(source_file) => match source_file.src {
+                Some(ref src) => (source_file.name.clone(), src.clone()),
+                None => return,
+            },
+            None => return,
+        };
+
+        // Edits to the root file that haven't been parsed yet would be lost.
+        if let Some(source_id) = self.pending_reparse.borrow_mut().take() {
+            glib::source_remove(source_id);
+            self.reparse();
+        }
+
+        // Not editable while swapping, so the new text isn't taken as an edit.
+        self.source_view.set_editable(false);
+        self.switching_file.set(true);
+        self.buffer.set_text(&src);
+        self.switching_file.set(false);
+        self.shown_file.set(file);
+        self.update_editable();
+        self.source_file_label.set_text(&name);
+    }
+
+    /// Starts watching the document's file, replacing any previous watch.
+    /// Monitoring is best-effort: if the file can't be watched, it isn't.
+    pub fn watch_file(document: &Rc<Document>) {
+        let monitor = document.path()
+            .and_then(|path| gio::File::new_for_path(path).monitor_file(gio::FILE_MONITOR_NONE, None).ok());
+        if let Some(ref monitor) = monitor {
+            let weak = Rc::downgrade(document);
+            monitor.connect_changed(move |_, _, _, event| {
+                // Editors either rewrite the file or replace it with a new one.
+                if event != FileMonitorEvent::ChangesDoneHint && event != FileMonitorEvent::Created {
+                    return;
+                }
+                if let Some(document) = weak.upgrade() {
+                    Document::schedule_reload(&document);
+                }
+            });
+        }
+        *document.monitor.borrow_mut() = monitor;
+    }
+
+    fn schedule_reload(document: &Rc<Document>) {
+        if let Some(source_id) = document.pending_reload.borrow_mut().take() {
+            glib::source_remove(source_id);
+        }
+
+        let weak: Weak<Document> = Rc::downgrade(document);
+        let source_id = glib::timeout_add(REPARSE_DELAY_MS, move || {
+            if let Some(document) = weak.upgrade() {
+                document.pending_reload.borrow_mut().take();
+                document.reload_changed_file();
+            }
+            glib::Continue(false)
+        });
+        *document.pending_reload.borrow_mut() = Some(source_id);
+    }
+
+    /// Reloads the file after it changed on disk, keeping the cursor, the
+    /// scroll positions and the tree's expanded rows and selection. Edits
+    /// made here that the file would overwrite are kept instead.
+    fn reload_changed_file(&self) {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return,
+        };
+        let mut contents = String::new();
+        let read = File::open(&path).and_then(|mut file| file.read_to_string(&mut contents));
+        let old_text = self.text().unwrap_or_default();
+        if read.is_err() || self.buffer.get_modified() || old_text == contents {
+            return;
+        }
+
+        let adjustments: Vec<_> = [self.source_view.get_vadjustment(), self.syntax_tree_view.get_vadjustment()]
+            .iter()
+            .filter_map(|adjustment| adjustment.clone())
+            .map(|adjustment| {
+                let value = adjustment.get_value();
+                (adjustment, value)
+            })
+            .collect();
+        let cursor = self.buffer.get_property_cursor_position();
+        let selected = self.selected_node();
+
+        // The tree keeps its state by itself; the cursor would jump to the end.
+        self.switching_file.set(true);
+        let result = self.load(&path);
+        let cursor_iter = self.buffer.get_iter_at_offset(cursor);
+        self.buffer.place_cursor(&cursor_iter);
+        self.switching_file.set(false);
+        if result.is_err() {
+            return;
+        }
+
+        // The tree restores the selection by its path, which points at another
+        // node if one was inserted before it. Look the node up again instead.
+        if let Some(selected) = selected {
+            self.reselect_node(&selected, &old_text, &contents);
+        }
+
+        // Scroll back once the new text has been laid out.
+        glib::idle_add(move || {
+            for &(ref adjustment, value) in &adjustments {
+                adjustment.set_value(value);
+            }
+            glib::Continue(false)
+        });
+    }
+
+    fn schedule_reparse(document: &Rc<Document>) {
+        if !document.source_view.get_editable() {
+            return;
+        }
+        if let Some(source_id) = document.pending_reparse.borrow_mut().take() {
+            glib::source_remove(source_id);
+        }
+
+        let weak: Weak<Document> = Rc::downgrade(document);
+        let source_id = glib::timeout_add(REPARSE_DELAY_MS, move || {
+            if let Some(document) = weak.upgrade() {
+                document.pending_reparse.borrow_mut().take();
+                document.reparse();
+            }
+            glib::Continue(false)
+        });
+        *document.pending_reparse.borrow_mut() = Some(source_id);
+    }
+
+    /// Applies `settings.editable` to the source view.
+    pub fn update_editable(&self) {
+        let editable = self.settings.editable.get() || self.scratch.get();
+        self.source_view.set_editable(editable && self.shown_file.get() == 0);
+    }
+
+    fn update_title(&self) {
+        self.tab_label.set_text(&self.title());
+        self.tab_label.set_tooltip_text(Some(self.source_name().as_str()));
+    }
+
+    /// Redraws spans after `settings.span_format` changed.
+    pub fn update_span_format(&self) {
+        self.syntax_tree_view.queue_draw();
+        self.diagnostics_view.queue_draw();
+        self.tokens_view.queue_draw();
+        self.query_results_view.queue_draw();
+
+        // Re-select to rebuild the properties list with the new format.
+        let selection = self.syntax_tree_view.get_selection();
+        if let Some((_, iter)) = selection.get_selected() {
+            selection.unselect_all();
+            selection.select_iter(&iter);
+        }
+    }
+
+    /// Replaces the syntax tree, diagnostics and tokens models, keeping
+    /// expanded rows and the selection of nodes that are still present.
+    fn show_parsed(&self, parsed: ParsedSource) {
+        *self.positions.borrow_mut() = parsed.positions;
+
+        let store = new_syntax_tree_store(&parsed.nodes);
+        *self.syntax_tree_store.borrow_mut() = Some(store.clone());
+        let searching = self.apply_search();
+        let filter = TreeModelFilter::new(&store, None);
+        filter.set_visible_column(AstModelColumns::Visible as i32);
+
+        let state = TreeViewState::save(&self.syntax_tree_view);
+        self.syntax_tree_view.set_model(Some(&filter));
+        state.restore(&self.syntax_tree_view);
+        if searching {
+            self.syntax_tree_view.expand_all();
+        }
+
+        self.diagnostics_view.set_model(Some(&new_diagnostics_store(&parsed.diagnostics)));
+        self.diagnostics_view.expand_all();
+
+        self.tokens_view.set_model(Some(&new_tokens_store(&parsed.tokens)));
+
+        let outline = outline::build_outline(&parsed.nodes, &self.positions.borrow());
+        self.outline_view.set_model(Some(&new_outline_store(&outline)));
+        self.outline_view.expand_all();
+        if let Some(root) = self.positions.borrow().root() {
+            if self.shown_file.get() == 0 {
+                self.source_file_label.set_text(&root.name);
+            }
+        }
+
+        *self.span_index.borrow_mut() = SpanIndex::new(&parsed.nodes);
+        *self.nodes.borrow_mut() = parsed.nodes;
+        self.run_query();
+
+        let chain = self.nodes_at_cursor(&store, self.buffer.get_property_cursor_position());
+        self.show_breadcrumbs(&store, &chain);
+    }
+
+    /// The node at the cursor in the shown file and its ancestors, outermost
+    /// first.
+    fn nodes_at_cursor(&self, store: &TreeStore, cursor: i32) -> Vec<TreeIter> {
+        let byte_pos = self.positions.borrow().file_byte_pos(self.shown_file.get(), cursor as u32);
+        match byte_pos {
+            Some(byte_pos) => store.find_node_by_pos(&self.span_index.borrow(), byte_pos),
+            None => vec![],
+        }
+    }
+
+    /// Fills the breadcrumb bar with the nodes of `chain`; clicking a crumb
+    /// selects its node in the tree.
+    fn show_breadcrumbs(&self, store: &TreeStore, chain: &[TreeIter]) {
+        for child in self.breadcrumb_box.get_children() {
+            self.breadcrumb_box.remove(&child);
+        }
+        for (i, iter) in chain.iter().enumerate() {
+            let path = match store.get_path(iter) {
+                Some(path) => path,
+                None => continue,
+            };
+            if i > 0 {
+                self.breadcrumb_box.pack_start(&Label::new(Some("›")), false, false, 0);
+            }
+            let crumb = Button::new_with_label(&crumb_label(&store.get_type(iter), &store.get_kind(iter)));
+            crumb.set_relief(ReliefStyle::None);
+            let tree_view = self.syntax_tree_view.clone();
+            crumb.connect_clicked(move |_| select_store_path(&tree_view, &path));
+            self.breadcrumb_box.pack_start(&crumb, false, false, 0);
+        }
+        self.breadcrumb_box.show_all();
+    }
+
+    /// Hides the nodes that don't match the search entry, along with their
+    /// subtrees unless something below matches. Returns whether a search is
+    /// active.
+    fn apply_search(&self) -> bool {
+        let filter = NodeFilter::parse(&self.search_entry.get_text().unwrap_or_default());
+        let hits = match *self.syntax_tree_store.borrow() {
+            Some(ref store) => store.apply_filter(filter.as_ref()),
+            None => vec![],
+        };
+        let status = match (filter.is_some(), hits.len()) {
+            (false, _) => String::new(),
+            (true, 0) => "No matches".to_owned(),
+            (true, 1) => "1 match".to_owned(),
+            (true, n) => format!("{} matches", n),
+        };
+        self.search_status.set_text(&status);
+        self.search_previous_button.set_sensitive(hits.len() > 1);
+        self.search_next_button.set_sensitive(hits.len() > 1);
+        *self.search_hits.borrow_mut() = hits;
+        self.current_hit.set(None);
+        filter.is_some()
+    }
+
+    /// Selects the next (or previous) search hit, wrapping around at the ends.
+    fn show_hit(&self, forward: bool) {
+        let count = self.search_hits.borrow().len();
+        if count == 0 {
+            return;
+        }
+        let index = match (self.current_hit.get(), forward) {
+            (None, _) => 0,
+            (Some(index), true) => (index + 1) % count,
+            (Some(index), false) => (index + count - 1) % count,
+        };
+        self.current_hit.set(Some(index));
+        self.search_status.set_text(&format!("{} of {}", index + 1, count));
+
+        let path = self.search_hits.borrow()[index].clone();
+        self.select_store_path(&path);
+    }
+
+    /// Runs the query in the query entry and lists the matching nodes.
+    fn run_query(&self) {
+        let text = self.query_entry.get_text().unwrap_or_default();
+        if text.trim().is_empty() {
+            self.query_status.set_text("");
+            self.query_results_view.set_model(None::<&TreeModel>);
+            return;
+        }
+
+        match Query::parse(&text) {
+            Ok(query) => {
+                let store = ListStore::new_query_results_store();
+                let nodes = self.nodes.borrow();
+                let results = query.find_all(&nodes);
+                for found in &results {
+                    store.insert_result(found);
+                }
+                let status = match results.len() {
+                    0 => "No matches".to_owned(),
+                    1 => "1 match".to_owned(),
+                    n => format!("{} matches", n),
+                };
+                self.query_status.set_text(&status);
+                self.query_results_view.set_model(Some(&store));
+            }
+            Err(e) => {
+                self.query_status.set_text(&e.to_string());
+                self.query_results_view.set_model(None::<&TreeModel>);
+            }
+        }
+    }
+
+    /// The selected node and where it starts, to find it again in the tree
+    /// of a new version of the file.
+    fn selected_node(&self) -> Option<SelectedNode> {
+        let (model, iter) = self.syntax_tree_view.get_selection().get_selected()?;
+        let (lo, _) = model.get_span(&iter)?;
+        let positions = self.positions.borrow();
+        let file = positions.file_index_for_pos(lo)?;
+        Some(SelectedNode {
+            ancestry: model.get_ancestry(&iter),
+            file: file,
+            offset: positions.files()[file].byte_offset(lo),
+        })
+    }
+
+    /// Selects the node `selected` was in the tree before the root file's
+    /// text changed from `old_text` to `new_text`, or nothing if it's gone.
+    fn reselect_node(&self, selected: &SelectedNode, old_text: &str, new_text: &str) {
+        let store = match *self.syntax_tree_store.borrow() {
+            Some(ref store) => store.clone(),
+            None => return,
+        };
+        // Only the root file changed; the modules it loads just moved.
+        let offset = if selected.file == 0 {
+            offset_after_edit(old_text, new_text, selected.offset)
+        }
+        else {
+            Some(selected.offset)
+        };
+        let lo = offset.and_then(|offset| {
+            self.positions.borrow().files().get(selected.file).map(|file| file.start_pos + offset)
+        });
+        let found = lo.and_then(|lo| store.find_node_by_ancestry(&selected.ancestry, lo))
+            .and_then(|iter| store.get_path(&iter));
+        match found {
+            Some(path) => self.select_store_path(&path),
+            None => self.syntax_tree_view.get_selection().unselect_all(),
+        }
+    }
+
+    /// Selects the node at `path` in the unfiltered syntax tree store, if
+    /// the search filter doesn't hide it.
+    fn select_store_path(&self, path: &TreePath) {
+        select_store_path(&self.syntax_tree_view, path);
+    }
+
+    fn select_node(&self, model: &TreeModel, iter: &TreeIter) {
+        select_row(&self.syntax_tree_view, model, iter);
+    }
+
+    /// Highlights the span, first switching the buffer to the file it's in.
+    fn highlight(&self, tag: &str, span: (u32, u32)) {
+        let file = self.positions.borrow().file_index_for_pos(span.0);
+        if let Some(file) = file {
+            self.show_file(file);
+            highlight_span(&self.buffer, &self.source_view, &self.positions.borrow(), file, tag, span);
+        }
+    }
+
+    fn clear_highlight(&self, tag: &str) {
+        let (start_iter, end_iter) = self.buffer.get_bounds();
+        self.buffer.remove_tag_by_name(tag, &start_iter, &end_iter);
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::{self, Write};
+use ast_node::AstNode;
+
+/// Writes the tree as indented `Type Kind [lo..hi)` lines, the same
+/// information the Type/Kind/Span columns show in the GUI.
+pub(crate) fn dump_nodes<W: Write>(out: &mut W, nodes: &[AstNode]) -> io::Result<()> {
+    for node in nodes {
+        dump_node(out, node, 0)?;
+    }
+    Ok(())
+}
+
+fn dump_node<W: Write>(out: &mut W, node: &AstNode, depth: usize) -> io::Result<()> {
+    write!(out, "{:indent$}{}", "", node.ty, indent = depth * 2)?;
+    if !node.kind.is_empty() {
+        write!(out, " {}", node.kind)?;
+    }
+    if let Some((lo, hi)) = node.span {
+        write!(out, " [{}..{})", lo, hi)?;
+    }
+    if let Some(ref expansion) = node.expansion {
+        write!(out, " (expanded from {}!)", expansion.macro_name)?;
+    }
+    writeln!(out)?;
+
+    for child in &node.children {
+        dump_node(out, child, depth + 1)?;
+    }
+    Ok(())
+}
@@ -3,6 +3,8 @@ use glib;
 error_chain!{
     foreign_links {
         GlibBoolError(glib::BoolError);
+        Io(::std::io::Error);
+        Json(::serde_json::Error);
         // None(::std::option::NoneError);
         // Diesel(DieselError);
         // Io(::std::io::Error) #[cfg(unix)];
@@ -20,5 +22,20 @@ error_chain!{
             description("unknown toolchain version"), // note the ,
             display("Downcast from '{}' to '{}' failed", from, to), // trailing comma is allowed
         }
+
+        ParseFailed(path: String) {
+            description("could not parse source file")
+            display("could not parse '{}'", path)
+        }
+
+        Usage(message: String) {
+            description("invalid command line")
+            display("{}", message)
+        }
+
+        InvalidQuery(message: String) {
+            description("invalid query")
+            display("invalid query: {}", message)
+        }
     }
 }
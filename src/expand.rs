@@ -0,0 +1,173 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use syntex_syntax::ast::{Attribute, Crate, Ident, Item, ItemKind, Mac, Name, NodeId, Path};
+use syntex_syntax::ext::base::{Determinacy, ExtCtxt, MacroExpanderFn, MacroKind, Resolver, SyntaxExtension};
+use syntex_syntax::ext::expand::{Expansion, ExpansionConfig, Invocation, InvocationKind};
+use syntex_syntax::ext::hygiene::Mark;
+use syntex_syntax::ext::source_util;
+use syntex_syntax::ext::tt::macro_rules;
+use syntex_syntax::feature_gate::Features;
+use syntex_syntax::parse::ParseSess;
+use syntex_syntax::print::pprust;
+use syntex_syntax::ptr::P;
+use syntex_syntax::visit::{self, Visitor};
+
+use diagnostics::catch_fatal;
+
+/// Name the expanded crate gets, e.g. for `module_path!()`.
+const CRATE_NAME: &str = "main";
+
+/// Runs macro expansion over a parsed crate.
+///
+/// Only `macro_rules!` macros and the built-ins implemented inside
+/// `syntex_syntax` itself can be expanded. Derives, attribute macros and
+/// compiler built-ins like `format_args!` live in the compiler, so those
+/// items are kept as they are and such invocations are reported as warnings.
+///
+/// A macro that fails fatally, e.g. a malformed `macro_rules!` definition,
+/// stops the expansion; the crate is then returned unexpanded.
+pub(crate) fn expand_crate(sess: &ParseSess, krate: Crate) -> Crate {
+    let unexpanded = krate.clone();
+    let expanded = catch_fatal(|| {
+        let features = Features::new();
+        let mut ecfg = ExpansionConfig::default(CRATE_NAME.to_owned());
+        ecfg.features = Some(&features);
+
+        let mut resolver = MacroResolver::new(sess);
+        register_builtins(&mut resolver);
+
+        let mut cx = ExtCtxt::new(sess, ecfg, &mut resolver);
+        cx.monotonic_expander().expand_crate(krate)
+    });
+    match expanded {
+        Some(krate) => krate,
+        None => {
+            sess.span_diagnostic.warn("macro expansion failed, showing the tree as written");
+            unexpanded
+        }
+    }
+}
+
+fn register_builtins(resolver: &mut MacroResolver) {
+    let builtins: &[(&str, MacroExpanderFn)] = &[
+        ("line", source_util::expand_line),
+        ("column", source_util::expand_column),
+        ("file", source_util::expand_file),
+        ("stringify", source_util::expand_stringify),
+        ("module_path", source_util::expand_mod),
+        ("include", source_util::expand_include),
+        ("include_str", source_util::expand_include_str),
+        ("include_bytes", source_util::expand_include_bytes),
+    ];
+    for &(name, expander) in builtins {
+        let ext = SyntaxExtension::NormalTT(Box::new(expander), None, false);
+        resolver.add_builtin(Ident::from_str(name), Rc::new(ext));
+    }
+}
+
+/// A flat, scope-less stand-in for rustc's resolver: every macro is looked
+/// up by name, whichever module it was defined in.
+struct MacroResolver<'a> {
+    sess: &'a ParseSess,
+    features: RefCell<Features>,
+    last_node_id: usize,
+    macros: HashMap<Name, Rc<SyntaxExtension>>,
+}
+
+impl<'a> MacroResolver<'a> {
+    fn new(sess: &'a ParseSess) -> MacroResolver<'a> {
+        MacroResolver {
+            sess: sess,
+            features: RefCell::new(Features::new()),
+            last_node_id: 0,
+            macros: HashMap::new(),
+        }
+    }
+}
+
+impl<'a> Resolver for MacroResolver<'a> {
+    fn next_node_id(&mut self) -> NodeId {
+        self.last_node_id += 1;
+        NodeId::new(self.last_node_id)
+    }
+
+    fn get_module_scope(&mut self, _id: NodeId) -> Mark {
+        Mark::root()
+    }
+
+    fn eliminate_crate_var(&mut self, item: P<Item>) -> P<Item> {
+        item
+    }
+
+    fn is_whitelisted_legacy_custom_derive(&self, _name: Name) -> bool {
+        false
+    }
+
+    fn visit_expansion(&mut self, _mark: Mark, expansion: &Expansion, _derives: &[Mark]) {
+        let mut collector = MacroDefCollector { defs: vec![] };
+        expansion.visit_with(&mut collector);
+        for def in collector.defs {
+            let ext = macro_rules::compile(self.sess, &self.features, &def);
+            self.macros.insert(def.ident.name, Rc::new(ext));
+        }
+    }
+
+    fn add_builtin(&mut self, ident: Ident, ext: Rc<SyntaxExtension>) {
+        self.macros.insert(ident.name, ext);
+    }
+
+    fn resolve_imports(&mut self) {}
+
+    fn find_legacy_attr_invoc(&mut self, _attrs: &mut Vec<Attribute>) -> Option<Attribute> {
+        None
+    }
+
+    fn resolve_invoc(&mut self, invoc: &mut Invocation, scope: Mark, force: bool)
+                     -> Result<Option<Rc<SyntaxExtension>>, Determinacy> {
+        match invoc.kind {
+            InvocationKind::Bang { ref mac, .. } => {
+                self.resolve_macro(scope, &mac.node.path, MacroKind::Bang, force).map(Some)
+            }
+            // Leave the annotated item untouched.
+            InvocationKind::Attr { .. } | InvocationKind::Derive { .. } => Ok(None),
+        }
+    }
+
+    fn resolve_macro(&mut self, _scope: Mark, path: &Path, _kind: MacroKind, force: bool)
+                     -> Result<Rc<SyntaxExtension>, Determinacy> {
+        if path.segments.len() == 1 {
+            if let Some(ext) = self.macros.get(&path.segments[0].identifier.name) {
+                return Ok(ext.clone());
+            }
+        }
+        // It may still be defined by a macro that hasn't been expanded yet.
+        if !force {
+            return Err(Determinacy::Undetermined);
+        }
+
+        let message = format!("macro `{}!` can't be expanded outside the compiler",
+                              pprust::path_to_string(path));
+        self.sess.span_diagnostic.span_warn(path.span, &message);
+        Err(Determinacy::Determined)
+    }
+
+    fn check_unused_macros(&self) {}
+}
+
+/// Collects `macro_rules!` definitions, including ones nested in functions.
+struct MacroDefCollector {
+    defs: Vec<Item>,
+}
+
+impl<'ast> Visitor<'ast> for MacroDefCollector {
+    fn visit_item(&mut self, item: &'ast Item) {
+        if let ItemKind::MacroDef(..) = item.node {
+            self.defs.push(item.clone());
+        }
+        visit::walk_item(self, item);
+    }
+
+    // Unexpanded invocations are placeholders at this point.
+    fn visit_mac(&mut self, _mac: &'ast Mac) {}
+}
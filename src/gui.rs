@@ -1,162 +1,431 @@
-use std::path::Path;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::io::{BufWriter, Read};
 use std::fs::File;
-use std::env;
+use std::rc::Rc;
+use std::cell::RefCell;
 use echain::{ErrorKind, Result};
+use gdk;
+use glib;
 use gtk;
 use gtk::prelude::*;
-use gtk::{Builder, Window, WidgetExt, TreeView, TreeViewExt, TreeViewColumn, CellRendererText, TreeStore, TreeModel, TextTag};
-use sourceview::{Buffer, BufferExt, LanguageManager, LanguageManagerExt, View};
-use syntex_syntax::codemap::FilePathMapping;
-use syntex_syntax::parse::{self, ParseSess};
-use syntex_syntax::visit::walk_crate;
-
-use visitor::TreeVisitor;
-use tree_column_set_data_func_ext::TreeViewColumnSetCellDataFuncExt;
-use ast_model_extensions::{AstModelExt, AstModelColumns, AstPropertiesColumns};
-
-macro_rules! column {
-    ($tree:expr, $col:ident, $cell:ident, $type:ident, $title:expr, $expand:expr) => {
-        let $col = TreeViewColumn::new();
-        $col.set_title($title);
-        let $cell = $type::new();
-        $col.pack_end(&$cell, $expand);
-        $tree.append_column(&$col);
-    };
-}
+use gtk::{Builder, Window, Widget, WidgetExt, Notebook, IconSize, ReliefStyle, Orientation};
+use gtk::{Menu, MenuItem, CheckMenuItem, RadioMenuItem, FileChooserDialog, FileChooserAction, FileFilter, MessageDialog, MessageType, ButtonsType, ResponseType};
+use gtk::{HeaderBar, Button, RecentChooserMenu, RecentManager, TargetEntry};
 
-fn add_ast_columns(tree: &TreeView) {
-    column!(tree, type_col, type_cell, CellRendererText, "Type", true);
-    type_col.add_attribute(&type_cell, "text", AstModelColumns::Type as i32);
-    column!(tree, kind_col, kind_cell, CellRendererText, "Kind", true);
-    kind_col.add_attribute(&kind_cell, "text", AstModelColumns::Kind as i32);
+use json;
+use ast_node::AstNode;
+use document::{Document, ViewSettings};
+use cli::{source_args, SourceArg};
+use diff_window::{DiffSource, DiffWindow};
+use position::SpanFormat;
+use templates::TEMPLATES;
 
-    column!(tree, span_col, span_cell, CellRendererText, "Span", false);
-    span_col.set_cell_data_func(&span_cell, move |_column, cell, model, iter| {
-        let text_cell = cell.clone().downcast::<CellRendererText>()
-            .expect("Couldn't downcast to CellRendererText");
+/// Asks for a `.rs` file to open.
+fn choose_file(window: &Window) -> Option<PathBuf> {
+    let dialog = FileChooserDialog::new(Some("Open"), Some(window), FileChooserAction::Open);
+    dialog.add_button("_Cancel", ResponseType::Cancel.into());
+    dialog.add_button("_Open", ResponseType::Accept.into());
 
-        if let Some((lo, hi)) = model.get_span(iter) {
-            text_cell.set_property_text(Some(&format!("[{}..{})", lo, hi)));
-        }
-        else {
-            text_cell.set_property_text(Some(""));
-        }
-    });
-}
+    let filter = FileFilter::new();
+    filter.set_name("Rust source");
+    filter.add_pattern("*.rs");
+    dialog.add_filter(&filter);
 
-fn add_properties_columns(list: &TreeView) {
-    column!(list, name_col, name_cell, CellRendererText, "Property", true);
-    name_col.add_attribute(&name_cell, "text", AstPropertiesColumns::Name as i32);
-    column!(list, value_col, value_cell, CellRendererText, "Value", true);
-    value_col.add_attribute(&value_cell, "text", AstPropertiesColumns::Value as i32);
+    let response = dialog.run();
+    let path = dialog.get_filename();
+    dialog.destroy();
+
+    if response == ResponseType::Accept.into() { path } else { None }
 }
 
-fn open_file<T: AsRef<Path>>(path: T, buffer: &Buffer) {
-    let file = File::open(&path).expect("Couldn't open file");
-    let mut reader = BufReader::new(file);
-    let mut contents = String::new();
-    reader.read_to_string(&mut contents).expect("Couldn't read the file");
-    buffer.set_text(&contents);
+/// Asks where to save a file, suggesting `name`.
+fn choose_save_file(window: &Window, title: &str, name: &str) -> Option<PathBuf> {
+    let dialog = FileChooserDialog::new(Some(title), Some(window), FileChooserAction::Save);
+    dialog.add_button("_Cancel", ResponseType::Cancel.into());
+    dialog.add_button("_Save", ResponseType::Accept.into());
+    dialog.set_do_overwrite_confirmation(true);
+    dialog.set_current_name(name);
+
+    let response = dialog.run();
+    let path = dialog.get_filename();
+    dialog.destroy();
 
-    let lang_manager = LanguageManager::new();
+    if response == ResponseType::Accept.into() { path } else { None }
+}
 
-    if let Some(lang) = lang_manager.guess_language(path.as_ref().to_str(), None) {
-        buffer.set_language(Some(&lang));
+/// Remembers the file in GTK's recently used list, which outlives the session.
+fn add_recent_file(path: &Path) {
+    let uri = path.canonicalize().ok()
+        .and_then(|path| glib::filename_to_uri(path, None).ok());
+    if let (Some(manager), Some(uri)) = (RecentManager::get_default(), uri) {
+        manager.add_item(&uri);
     }
 }
 
+fn show_error(window: &Window, message: &str) {
+    let dialog = MessageDialog::new(Some(window), gtk::DIALOG_MODAL, MessageType::Error, ButtonsType::Ok, message);
+    dialog.run();
+    dialog.destroy();
+}
+
+fn export_json(window: &Window, nodes: &[AstNode]) -> Result<()> {
+    if let Some(path) = choose_save_file(window, "Export as JSON", "syntax_tree.json") {
+        let file = File::create(path)?;
+        json::write_json(BufWriter::new(file), nodes)?;
+    }
+    Ok(())
+}
 
-fn parse_file<T: AsRef<Path>>(path: T) -> TreeStore {
-    let path_mapping = FilePathMapping::empty();
-    let parse_session = ParseSess::new(path_mapping);
+fn read_diff_source(path: &Path) -> Result<DiffSource> {
+    let mut text = String::new();
+    File::open(path)?.read_to_string(&mut text)?;
+    Ok(DiffSource {
+        name: path.to_string_lossy().into_owned(),
+        text: text,
+    })
+}
 
-    let krate = match parse::parse_crate_from_file(path.as_ref(), &parse_session) {
-        // There may be parse errors that the parser recovered from, which we
-        // want to treat as an error.
-        Ok(_) if parse_session.span_diagnostic.has_errors() => Err(None),
-        Ok(krate) => Ok(krate),
-        Err(e) => Err(Some(e)),
+/// Opens a diff of `old` against the document's current text, which may
+/// have unsaved edits.
+fn compare_document(window: &Window, document: &Document, old: &Path, expand: bool) -> Result<()> {
+    let old = read_diff_source(old)?;
+    let new = DiffSource {
+        name: format!("{} (buffer)", document.source_name()),
+        text: document.text().unwrap_or_default(),
     };
+    let diff_window = DiffWindow::new(&old, &new, expand)?;
+    diff_window.window.set_transient_for(Some(window));
+    diff_window.window.show_all();
+    Ok(())
+}
 
-    let mut vis = TreeVisitor::new();
-    walk_crate(&mut vis, &krate.expect("Could not walk crate"));
+/// `syntax_visualizer --diff <old> <new>`: shows only the diff window.
+pub(crate) fn diff_main(args: &[String]) -> Result<()> {
+    if args.len() != 2 {
+        bail!(ErrorKind::Usage("usage: syntax_visualizer --diff <old> <new>".to_owned()));
+    }
+    gtk::init()?;
+    let old = read_diff_source(Path::new(&args[0]))?;
+    let new = read_diff_source(Path::new(&args[1]))?;
+    let diff_window = DiffWindow::new(&old, &new, false)?;
+    diff_window.window.connect_delete_event(|_, _| {
+        gtk::main_quit();
+        Inhibit(false)
+    });
+    diff_window.window.show_all();
 
-    vis.tree
+    gtk::main();
+    Ok(())
 }
 
-macro_rules! get_widget {
-    ($builder:expr, $name:ident, $type:ty) => {
-        let $name: $type = $builder.get_object(stringify!($name))
-            .ok_or(ErrorKind::WidgetNotFound(stringify!($name)))?;
-    }
+/// The open documents, in the same order as the notebook pages.
+type Documents = Rc<RefCell<Vec<Rc<Document>>>>;
+
+fn current_document(notebook: &Notebook, documents: &Documents) -> Option<Rc<Document>> {
+    let page = notebook.get_current_page()?;
+    documents.borrow().get(page as usize).cloned()
+}
+
+/// The widgets that depend on the current document.
+#[derive(Clone)]
+struct HeaderWidgets {
+    header_bar: HeaderBar,
+    reload_button: Button,
+    save_as_item: MenuItem,
+    compare_saved_item: MenuItem,
 }
 
-pub(crate) fn gui_main() -> Result<()> {
+/// Shows the current document's path in the header bar, and enables what
+/// needs a document or a file on disk.
+fn update_header(header: &HeaderWidgets, document: Option<&Document>) {
+    let path = document.and_then(|document| document.path());
+    let subtitle = path.as_ref().map(|path| path.to_string_lossy().into_owned());
+    header.header_bar.set_subtitle(subtitle.as_ref().map(|s| s.as_str()));
+    header.reload_button.set_sensitive(path.is_some());
+    header.save_as_item.set_sensitive(document.is_some());
+    header.compare_saved_item.set_sensitive(path.is_some());
+}
+
+/// Adds a page for the document, with a close button on its tab.
+fn add_tab(notebook: &Notebook, documents: &Documents, document: Rc<Document>) {
+    let tab = gtk::Box::new(Orientation::Horizontal, 4);
+    let close_button = Button::new_from_icon_name("window-close-symbolic", IconSize::Menu.into());
+    close_button.set_relief(ReliefStyle::None);
+    tab.pack_start(&document.tab_label, true, true, 0);
+    tab.pack_start(&close_button, false, false, 0);
+    tab.show_all();
+
+    let panel = document.panel.clone();
+    documents.borrow_mut().push(document);
+    let page = notebook.append_page(&panel, Some(&tab));
+    notebook.set_current_page(Some(page));
+
+    let notebook = notebook.clone();
+    let documents = documents.clone();
+    close_button.connect_clicked(move |_| {
+        if let Some(page) = notebook.page_num(&panel) {
+            documents.borrow_mut().remove(page as usize);
+            notebook.remove_page(Some(page));
+        }
+    });
+}
+
+pub(crate) fn gui_main(args: &[String]) -> Result<()> {
+    let sources = source_args(args)?;
     gtk::init()?;
     let glade_src = include_str!("syntax_visualizer.glade");
     let builder = Builder::new_from_string(glade_src);
 
     get_widget!(builder, main_window, Window);
-    get_widget!(builder, source_view, View);
-    get_widget!(builder, syntax_tree_view, TreeView);
-    get_widget!(builder, node_properties_view, TreeView);
-
-    let buffer: Buffer = source_view.get_buffer()
-        .ok_or(ErrorKind::WidgetNotFound("Buffer"))?
-        .downcast::<Buffer>()
-        .map_err(|_| ErrorKind::DowncastFailed("TextBuffer", "Buffer"))?;
-
-    // syntax_tree_view.set_headers_visible(false);
-    add_ast_columns(&syntax_tree_view);
-    add_properties_columns(&node_properties_view);
-
-    if let Some(path) = env::args().nth(1) {
-        let syntax_tree_store = parse_file(&path);
-        open_file(&path, &buffer);
-        syntax_tree_view.set_model(Some(&syntax_tree_store));
+    get_widget!(builder, header_bar, HeaderBar);
+    get_widget!(builder, open_button, Button);
+    get_widget!(builder, recent_menu, RecentChooserMenu);
+    get_widget!(builder, reload_button, Button);
+    get_widget!(builder, documents_notebook, Notebook);
+    get_widget!(builder, new_menu_item, MenuItem);
+    get_widget!(builder, templates_menu, Menu);
+    get_widget!(builder, save_as_menu_item, MenuItem);
+    get_widget!(builder, export_json_menu_item, MenuItem);
+    get_widget!(builder, compare_file_menu_item, MenuItem);
+    get_widget!(builder, compare_saved_menu_item, MenuItem);
+    get_widget!(builder, editable_menu_item, CheckMenuItem);
+    get_widget!(builder, span_bytes_menu_item, RadioMenuItem);
+    get_widget!(builder, span_line_col_menu_item, RadioMenuItem);
+    get_widget!(builder, span_chars_menu_item, RadioMenuItem);
+    get_widget!(builder, expand_macros_menu_item, CheckMenuItem);
+
+    let settings = Rc::new(ViewSettings::new());
+    let documents: Documents = Rc::new(RefCell::new(vec![]));
+
+    let open_document: Rc<Fn(&Path)> = {
+        let main_window = main_window.clone();
+        let documents_notebook = documents_notebook.clone();
+        let documents = documents.clone();
+        let settings = settings.clone();
+        Rc::new(move |path: &Path| {
+            let result = Document::new(settings.clone()).and_then(|document| {
+                document.load(path)?;
+                Document::watch_file(&document);
+                Ok(document)
+            });
+            match result {
+                Ok(document) => {
+                    add_tab(&documents_notebook, &documents, document);
+                    add_recent_file(path);
+                }
+                Err(e) => show_error(&main_window, &format!("Couldn't open {}: {}", path.display(), e)),
+            }
+        })
+    };
+
+    for source in &sources {
+        let text = match *source {
+            SourceArg::Path(ref path) => {
+                open_document(Path::new(path));
+                continue;
+            }
+            _ => source.text()?.unwrap_or_default(),
+        };
+        let document = Document::new(settings.clone())?;
+        document.load_text(source.name(), &text, source.fragment());
+        add_tab(&documents_notebook, &documents, document);
+    }
+
+    let new_scratchpad: Rc<Fn(&str)> = {
+        let main_window = main_window.clone();
+        let documents_notebook = documents_notebook.clone();
+        let documents = documents.clone();
+        let settings = settings.clone();
+        Rc::new(move |text: &str| {
+            match Document::new(settings.clone()) {
+                Ok(document) => {
+                    document.load_scratch(text);
+                    add_tab(&documents_notebook, &documents, document);
+                }
+                Err(e) => show_error(&main_window, &e.to_string()),
+            }
+        })
+    };
+    if sources.is_empty() {
+        new_scratchpad("");
+    }
+
+    let header = HeaderWidgets {
+        header_bar: header_bar.clone(),
+        reload_button: reload_button.clone(),
+        save_as_item: save_as_menu_item.clone(),
+        compare_saved_item: compare_saved_menu_item.clone(),
+    };
+    let documents_clone = documents.clone();
+    let header_clone = header.clone();
+    documents_notebook.connect_switch_page(move |_, page, _| {
+        // Look the document up by widget; during removal page numbers shift.
+        let document = documents_clone.borrow().iter()
+            .find(|document| document.panel.clone().upcast::<Widget>() == *page)
+            .cloned();
+        update_header(&header_clone, document.as_ref().map(|d| &**d));
+    });
+    let header_clone = header.clone();
+    documents_notebook.connect_page_removed(move |notebook, _, _| {
+        if notebook.get_n_pages() == 0 {
+            update_header(&header_clone, None);
+        }
+    });
+    let current = current_document(&documents_notebook, &documents);
+    update_header(&header, current.as_ref().map(|d| &**d));
+
+    let new_scratchpad_clone = new_scratchpad.clone();
+    new_menu_item.connect_activate(move |_| new_scratchpad_clone(""));
+
+    for &(label, text) in TEMPLATES {
+        let item = MenuItem::new_with_mnemonic(label);
+        let new_scratchpad = new_scratchpad.clone();
+        item.connect_activate(move |_| new_scratchpad(text));
+        templates_menu.append(&item);
     }
+    templates_menu.show_all();
 
-    let tag_table = buffer.get_tag_table().ok_or(ErrorKind::WidgetNotFound("TagTable"))?;
-    let tag_highlighted = TextTag::new("highlighted");
-    tag_highlighted.set_property_background(Some("#dcebff"));
-    tag_table.add(&tag_highlighted);
-
-    let syntax_tree_selection = syntax_tree_view.get_selection();
-    let buffer_clone = buffer.clone();
-    syntax_tree_selection.connect_changed(move |tree_selection| {
-        let (start_iter, end_iter) = buffer_clone.get_bounds();
-        buffer_clone.remove_tag_by_name("highlighted", &start_iter, &end_iter);
-
-        if let Some((model, iter)) = tree_selection.get_selected() {
-            let props = model.get_properties_list(&iter);
-            node_properties_view.set_model(Some(&props));
-
-            if let Some((lo, hi)) = model.get_span(&iter) {
-                let mut lo_iter = buffer_clone.get_iter_at_offset(lo as i32);
-                let hi_iter = buffer_clone.get_iter_at_offset(hi as i32);
-                buffer_clone.apply_tag_by_name("highlighted", &lo_iter, &hi_iter);
-                source_view.scroll_to_iter(&mut lo_iter, 0.1, false, 0.0, 0.0);
+    let main_window_clone = main_window.clone();
+    let documents_notebook_clone = documents_notebook.clone();
+    let documents_clone = documents.clone();
+    let header_clone = header.clone();
+    save_as_menu_item.connect_activate(move |_| {
+        let document = match current_document(&documents_notebook_clone, &documents_clone) {
+            Some(document) => document,
+            None => return,
+        };
+        let name = match document.path() {
+            Some(_) => document.title(),
+            None => "scratchpad.rs".to_owned(),
+        };
+        if let Some(path) = choose_save_file(&main_window_clone, "Save As", &name) {
+            match Document::save_as(&document, &path) {
+                Ok(()) => {
+                    add_recent_file(&path);
+                    update_header(&header_clone, Some(&document));
+                }
+                Err(e) => show_error(&main_window_clone, &format!("Couldn't save {}: {}", path.display(), e)),
             }
         }
-        else {
-            node_properties_view.set_model(None::<&TreeModel>);
+    });
+
+    let main_window_clone = main_window.clone();
+    let open_document_clone = open_document.clone();
+    open_button.connect_clicked(move |_| {
+        if let Some(path) = choose_file(&main_window_clone) {
+            open_document_clone(&path);
+        }
+    });
+
+    let open_document_clone = open_document.clone();
+    recent_menu.connect_item_activated(move |menu| {
+        let path = menu.get_current_uri()
+            .and_then(|uri| glib::filename_from_uri(&uri).ok())
+            .map(|(path, _)| path);
+        if let Some(path) = path {
+            open_document_clone(&path);
         }
     });
 
-    buffer.connect_property_cursor_position_notify(move |buffer| {
-        let pos = buffer.get_property_cursor_position();
-        println!("new cursor position: {}", pos);
-        let model = syntax_tree_view.get_model().expect("Couldnt get tree model");
-        if let Some(iter) = model.find_node_by_pos(pos) {
-            let path = model.get_path(&iter).expect("Could not get tree path");
-            syntax_tree_view.expand_to_path(&path);
-            syntax_tree_selection.select_iter(&iter);
-            syntax_tree_view.scroll_to_cell(&path, None, false, 0.0, 0.0);
+    let main_window_clone = main_window.clone();
+    let documents_notebook_clone = documents_notebook.clone();
+    let documents_clone = documents.clone();
+    reload_button.connect_clicked(move |_| {
+        if let Some(document) = current_document(&documents_notebook_clone, &documents_clone) {
+            if let Err(e) = document.reload() {
+                show_error(&main_window_clone, &e.to_string());
+            }
         }
     });
 
+    let targets = [TargetEntry::new("text/uri-list", gtk::TARGET_OTHER_APP, 0)];
+    main_window.drag_dest_set(gtk::DEST_DEFAULT_ALL, &targets, gdk::ACTION_COPY);
+    let open_document_clone = open_document.clone();
+    main_window.connect_drag_data_received(move |_, _, _, _, data, _, _| {
+        for uri in data.get_uris() {
+            if let Ok((path, _)) = glib::filename_from_uri(&uri) {
+                open_document_clone(&path);
+            }
+        }
+    });
+
+    let main_window_clone = main_window.clone();
+    let documents_notebook_clone = documents_notebook.clone();
+    let documents_clone = documents.clone();
+    export_json_menu_item.connect_activate(move |_| {
+        if let Some(document) = current_document(&documents_notebook_clone, &documents_clone) {
+            if let Err(e) = export_json(&main_window_clone, &document.nodes()) {
+                show_error(&main_window_clone, &e.to_string());
+            }
+        }
+    });
+
+    let main_window_clone = main_window.clone();
+    let documents_notebook_clone = documents_notebook.clone();
+    let documents_clone = documents.clone();
+    let settings_clone = settings.clone();
+    compare_file_menu_item.connect_activate(move |_| {
+        let document = match current_document(&documents_notebook_clone, &documents_clone) {
+            Some(document) => document,
+            None => return,
+        };
+        if let Some(path) = choose_file(&main_window_clone) {
+            if let Err(e) = compare_document(&main_window_clone, &document, &path, settings_clone.expand_macros.get()) {
+                show_error(&main_window_clone, &e.to_string());
+            }
+        }
+    });
+
+    let main_window_clone = main_window.clone();
+    let documents_notebook_clone = documents_notebook.clone();
+    let documents_clone = documents.clone();
+    let settings_clone = settings.clone();
+    compare_saved_menu_item.connect_activate(move |_| {
+        let document = match current_document(&documents_notebook_clone, &documents_clone) {
+            Some(document) => document,
+            None => return,
+        };
+        if let Some(path) = document.path() {
+            if let Err(e) = compare_document(&main_window_clone, &document, &path, settings_clone.expand_macros.get()) {
+                show_error(&main_window_clone, &e.to_string());
+            }
+        }
+    });
+
+    let settings_clone = settings.clone();
+    let documents_clone = documents.clone();
+    editable_menu_item.connect_toggled(move |item| {
+        settings_clone.editable.set(item.get_active());
+        for document in documents_clone.borrow().iter() {
+            document.update_editable();
+        }
+    });
+
+    let settings_clone = settings.clone();
+    let documents_clone = documents.clone();
+    expand_macros_menu_item.connect_toggled(move |item| {
+        settings_clone.expand_macros.set(item.get_active());
+        for document in documents_clone.borrow().iter() {
+            document.reparse();
+        }
+    });
+
+    for &(ref item, format) in &[(span_bytes_menu_item, SpanFormat::Bytes),
+                                 (span_line_col_menu_item, SpanFormat::LineCol),
+                                 (span_chars_menu_item, SpanFormat::Chars)] {
+        let settings = settings.clone();
+        let documents = documents.clone();
+        item.connect_toggled(move |item| {
+            if !item.get_active() {
+                return;
+            }
+            settings.span_format.set(format);
+            for document in documents.borrow().iter() {
+                document.update_span_format();
+            }
+        });
+    }
+
     main_window.connect_delete_event(|_, _| {
         gtk::main_quit();
         Inhibit(false)
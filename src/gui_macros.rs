@@ -0,0 +1,16 @@
+macro_rules! get_widget {
+    ($builder:expr, $name:ident, $type:ty) => {
+        let $name: $type = $builder.get_object(stringify!($name))
+            .ok_or(ErrorKind::WidgetNotFound(stringify!($name)))?;
+    }
+}
+
+macro_rules! column {
+    ($tree:expr, $col:ident, $cell:ident, $type:ident, $title:expr, $expand:expr) => {
+        let $col = TreeViewColumn::new();
+        $col.set_title($title);
+        let $cell = $type::new();
+        $col.pack_end(&$cell, $expand);
+        $tree.append_column(&$col);
+    };
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+use serde_json::{self, Value};
+
+use echain::Result;
+use ast_node::{AstNode, Property};
+
+/// Version of the JSON layout produced by `write_json`.
+/// Bump it whenever a field is added, renamed, removed or changes meaning.
+///
+/// Version 2 added the `children` of properties and the `expansion` of nodes.
+pub(crate) const JSON_SCHEMA_VERSION: u32 = 2;
+
+/// Converts the tree into the exported JSON document:
+///
+/// ```text
+/// { "version": 2,
+///   "nodes": [ { "type": "Item", "kind": "Fn",
+///                "span": { "lo": 0, "hi": 42 } | null,
+///                "expansion": { "macro": "vec",
+///                               "call_site": { "lo": 7, "hi": 19 } } | null,
+///                "properties": [ { "name": "Name", "value": "main",
+///                                  "children": [ ... ] } ],
+///                "children": [ ... ] } ] }
+/// ```
+pub(crate) fn nodes_to_json(nodes: &[AstNode]) -> Value {
+    json!({
+        "version": JSON_SCHEMA_VERSION,
+        "nodes": nodes.iter().map(node_to_json).collect::<Vec<_>>(),
+    })
+}
+
+fn node_to_json(node: &AstNode) -> Value {
+    let span = match node.span {
+        Some((lo, hi)) => json!({ "lo": lo, "hi": hi }),
+        None => Value::Null,
+    };
+    let expansion = match node.expansion {
+        Some(ref expansion) => json!({
+            "macro": expansion.macro_name,
+            "call_site": { "lo": expansion.call_site.0, "hi": expansion.call_site.1 },
+        }),
+        None => Value::Null,
+    };
+    json!({
+        "type": node.ty,
+        "kind": node.kind,
+        "span": span,
+        "expansion": expansion,
+        "properties": node.properties.iter().map(property_to_json).collect::<Vec<_>>(),
+        "children": node.children.iter().map(node_to_json).collect::<Vec<_>>(),
+    })
+}
+
+fn property_to_json(property: &Property) -> Value {
+    json!({
+        "name": property.name,
+        "value": property.value,
+        "children": property.children.iter().map(property_to_json).collect::<Vec<_>>(),
+    })
+}
+
+pub(crate) fn write_json<W: Write>(mut out: W, nodes: &[AstNode]) -> Result<()> {
+    serde_json::to_writer_pretty(&mut out, &nodes_to_json(nodes))?;
+    writeln!(out)?;
+    Ok(())
+}
@@ -2,18 +2,68 @@
 extern crate error_chain;
 extern crate glib_sys as glib_ffi;
 extern crate gtk_sys as ffi;
+extern crate gdk;
+extern crate gio;
 extern crate glib;
 extern crate gtk;
 extern crate sourceview;
+#[macro_use]
+extern crate serde_json;
 extern crate syntex_syntax;
 extern crate syntex_pos;
+extern crate syntex_errors;
+
+use std::env;
+use std::process;
 
 mod echain;
+mod ast_node;
+mod position;
+mod diagnostics;
+mod parser;
+mod expand;
+mod tokens;
+mod trivia;
+mod outline;
+mod span_index;
+mod node_filter;
+mod query;
+mod diff;
+mod dump;
+mod json;
+mod cli;
+mod templates;
+#[macro_use]
+mod gui_macros;
 mod gui;
+mod document;
+mod diff_window;
 mod visitor;
 mod tree_column_set_data_func_ext;
 mod ast_model_extensions;
+mod tree_view_state;
+mod diagnostics_model_extensions;
+mod tokens_model_extensions;
+mod outline_model_extensions;
+mod query_model_extensions;
 
 fn main() {
-    gui::gui_main().unwrap();
+    diagnostics::silence_fatal_errors();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(|a| a.as_str()) {
+        Some("--dump") | Some("dump") => cli::dump_main(&args[1..]),
+        Some("--json") | Some("json") => cli::json_main(&args[1..]),
+        Some("--query") | Some("query") => cli::query_main(&args[1..]),
+        Some("--diff") | Some("diff") => gui::diff_main(&args[1..]),
+        _ => gui::gui_main(&args),
+    };
+
+    if let Err(ref e) = result {
+        eprintln!("error: {}", e);
+        for cause in e.iter().skip(1) {
+            eprintln!("caused by: {}", cause);
+        }
+        process::exit(1);
+    }
 }
@@ -0,0 +1,36 @@
+/// A search bar query.
+///
+/// `Expr` matches nodes whose Type, Kind, or Name/Ident property is `Expr`;
+/// `Expr:MethodCall` matches on Type and Kind together, and either half may
+/// be left empty to match anything.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum NodeFilter {
+    Any(String),
+    TypeKind(String, String),
+}
+
+impl NodeFilter {
+    /// `None` for a blank query, which shows the whole tree.
+    pub fn parse(query: &str) -> Option<NodeFilter> {
+        let query = query.trim();
+        if query.is_empty() {
+            return None;
+        }
+        match query.find(':') {
+            Some(colon) => Some(NodeFilter::TypeKind(query[..colon].trim().to_owned(),
+                                                     query[colon + 1..].trim().to_owned())),
+            None => Some(NodeFilter::Any(query.to_owned())),
+        }
+    }
+
+    pub fn matches(&self, ty: &str, kind: &str, names: &[String]) -> bool {
+        match *self {
+            NodeFilter::Any(ref text) => {
+                ty == text || kind == text || names.iter().any(|name| name == text)
+            }
+            NodeFilter::TypeKind(ref want_ty, ref want_kind) => {
+                (want_ty.is_empty() || ty == want_ty) && (want_kind.is_empty() || kind == want_kind)
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::path::Path;
+use ast_node::AstNode;
+use position::PositionMap;
+
+/// A module of the crate and the file its contents were read from.
+#[derive(Clone, Debug)]
+pub(crate) struct ModuleOutline {
+    pub name: String,
+    pub file: String,
+    /// Span of the module's `Mod` node.
+    pub span: (u32, u32),
+    pub children: Vec<ModuleOutline>,
+}
+
+/// Builds the module tree of a crate, starting from the `Mod` under its
+/// `Crate` node.
+pub(crate) fn build_outline(nodes: &[AstNode], positions: &PositionMap) -> Vec<ModuleOutline> {
+    nodes.iter()
+        .filter(|node| node.ty == "Crate")
+        .filter_map(|node| node.children.iter().find(|child| child.ty == "Mod"))
+        .filter_map(|module| module_outline("crate", module, positions))
+        .collect()
+}
+
+fn module_outline(name: &str, module: &AstNode, positions: &PositionMap) -> Option<ModuleOutline> {
+    let span = module.span?;
+    let file = positions.file_for_pos(span.0)
+        .map(|file| file_name(&file.name))
+        .unwrap_or_default();
+    let mut children = vec![];
+    collect_modules(&module.children, positions, &mut children);
+
+    Some(ModuleOutline {
+        name: name.to_owned(),
+        file: file,
+        span: span,
+        children: children,
+    })
+}
+
+/// Finds `mod` items anywhere below `nodes`, e.g. also inside functions.
+fn collect_modules(nodes: &[AstNode], positions: &PositionMap, outline: &mut Vec<ModuleOutline>) {
+    for node in nodes {
+        if node.ty == "Item" && node.kind == "Mod" {
+            let name = node.properties.iter()
+                .find(|property| property.name == "Name")
+                .map(|property| property.value.as_str())
+                .unwrap_or("");
+            let module = node.children.iter()
+                .find(|child| child.ty == "Mod")
+                .and_then(|child| module_outline(name, child, positions));
+            if let Some(module) = module {
+                outline.push(module);
+                continue;
+            }
+        }
+        collect_modules(&node.children, positions, outline);
+    }
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use ast_node::{AstNode, Property};
+    use position::PositionMap;
+    use super::build_outline;
+
+    fn node(ty: &str, kind: &str, span: (u32, u32), children: Vec<AstNode>) -> AstNode {
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            span: Some(span),
+            children: children,
+            ..AstNode::default()
+        }
+    }
+
+    /// `mod name { ... }` as the visitor builds it: an `Item` holding a `Mod`.
+    fn module_item(name: &str, span: (u32, u32), children: Vec<AstNode>) -> AstNode {
+        let mut item = node("Item", "Mod", span, vec![node("Mod", "", span, children)]);
+        item.add_property(Property::new("Name", name));
+        item
+    }
+
+    #[test]
+    fn collects_nested_modules() {
+        let nodes = vec![
+            node("Crate", "", (0, 100), vec![
+                node("Attribute", "", (0, 10), vec![]),
+                node("Mod", "", (0, 100), vec![
+                    module_item("a", (0, 40), vec![module_item("b", (10, 30), vec![])]),
+                    node("Item", "Fn", (50, 100), vec![
+                        node("Block", "", (60, 100), vec![module_item("c", (70, 90), vec![])]),
+                    ]),
+                ]),
+            ]),
+        ];
+        let outline = build_outline(&nodes, &PositionMap::default());
+
+        assert_eq!(outline.len(), 1);
+        assert_eq!(outline[0].name, "crate");
+        assert_eq!(outline[0].span, (0, 100));
+        let names: Vec<_> = outline[0].children.iter().map(|module| module.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "c"]);
+        assert_eq!(outline[0].children[0].children[0].name, "b");
+        assert_eq!(outline[0].children[0].children[0].span, (10, 30));
+    }
+}
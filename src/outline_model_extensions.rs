@@ -0,0 +1,66 @@
+use gtk::prelude::*;
+use gtk::{TreeModel, TreeIter, TreeStore, TreeModelExt, TreeStoreExtManual};
+use outline::ModuleOutline;
+
+pub(crate) enum OutlineColumns {
+    Module = 0,
+    File = 1,
+    Lo = 2,
+    Hi = 3,
+}
+
+pub(crate) trait OutlineModelExt {
+    fn get_module_span(&self, iter: &TreeIter) -> (u32, u32);
+}
+
+macro_rules! get_outline_model_value {
+    ($model:expr, $iter:expr, $col:ident, $type:ty) => {
+        $model.get_value($iter, OutlineColumns::$col as i32)
+                    .get::<$type>().expect("Could not get value from TreeStore")
+    }
+}
+
+impl<O: IsA<TreeModel> + TreeModelExt> OutlineModelExt for O {
+    fn get_module_span(&self, iter: &TreeIter) -> (u32, u32) {
+        let lo = get_outline_model_value!(self, iter, Lo, u32);
+        let hi = get_outline_model_value!(self, iter, Hi, u32);
+        (lo, hi)
+    }
+}
+
+pub(crate) trait OutlineStoreExt {
+    fn new_outline_store() -> TreeStore;
+    fn insert_module(&self, iter: Option<&TreeIter>, module: &ModuleOutline) -> TreeIter;
+}
+
+impl<O: IsA<TreeStore> + TreeStoreExtManual> OutlineStoreExt for O {
+    fn new_outline_store() -> TreeStore {
+        TreeStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            u32::static_type(),
+            u32::static_type()
+        ])
+    }
+
+    fn insert_module(&self, iter: Option<&TreeIter>, module: &ModuleOutline) -> TreeIter {
+        let cols: Vec<u32> = vec![
+            OutlineColumns::Module as u32,
+            OutlineColumns::File as u32,
+            OutlineColumns::Lo as u32,
+            OutlineColumns::Hi as u32
+        ];
+        let vals: Vec<&ToValue> = vec![
+            &module.name,
+            &module.file,
+            &module.span.0,
+            &module.span.1
+        ];
+
+        let module_iter = self.insert_with_values(iter, None, &cols, &vals);
+        for child in &module.children {
+            self.insert_module(Some(&module_iter), child);
+        }
+        module_iter
+    }
+}
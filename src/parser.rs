@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::path::Path;
+use std::rc::Rc;
+use syntex_errors::Handler;
+use syntex_syntax::ast::{Crate, Expr, Item, Stmt};
+use syntex_syntax::codemap::{CodeMap, FilePathMapping};
+use syntex_syntax::parse::{self, ParseSess, PResult};
+use syntex_syntax::parse::token::Token;
+use syntex_syntax::ptr::P;
+use syntex_syntax::visit::Visitor;
+
+use ast_node::AstNode;
+use diagnostics::{catch_fatal, Diagnostic, CollectingEmitter};
+use echain::Result;
+use expand::expand_crate;
+use position::PositionMap;
+use tokens::{self, SourceToken};
+use trivia::attach_comments;
+use visitor::TreeVisitor;
+
+/// The syntax tree of a source file together with the diagnostics
+/// reported while parsing it. When the parser recovered from errors
+/// `nodes` holds the partial tree; on a fatal error it's empty.
+pub(crate) struct ParsedSource {
+    pub nodes: Vec<AstNode>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub has_errors: bool,
+    pub positions: PositionMap,
+    /// Raw tokens of the parsed file, trivia included.
+    pub tokens: Vec<SourceToken>,
+}
+
+/// What source text is parsed as. Anything but a whole crate is a snippet,
+/// whose tree is rooted at the expression, item or statement itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Fragment {
+    Crate,
+    Expr,
+    Item,
+    Stmt,
+}
+
+impl Fragment {
+    /// Name of the parsed input, for snippets that don't come from a file.
+    pub fn source_name(&self) -> &'static str {
+        match *self {
+            Fragment::Crate => "<buffer>",
+            Fragment::Expr => "<expr>",
+            Fragment::Item => "<item>",
+            Fragment::Stmt => "<stmt>",
+        }
+    }
+}
+
+enum ParsedFragment {
+    Crate(Crate),
+    Expr(P<Expr>),
+    Item(P<Item>),
+    Stmt(Stmt),
+}
+
+/// With `expand` set the tree is shown after macro expansion. Fails if the
+/// file can't be read; syntax errors end up in the diagnostics.
+pub(crate) fn parse_file<T: AsRef<Path>>(path: T, expand: bool) -> Result<ParsedSource> {
+    // The parser aborts on files it can't load instead of returning an error.
+    File::open(path.as_ref())?;
+    Ok(parse_with(|sess| parse::parse_crate_from_file(path.as_ref(), sess).map(|krate| Some(ParsedFragment::Crate(krate))), expand))
+}
+
+/// Parses in-memory source text, e.g. the contents of the edited buffer.
+/// `name` is only used as the file name in the codemap.
+pub(crate) fn parse_source(name: &str, source: &str, expand: bool) -> ParsedSource {
+    parse_fragment(name, source, Fragment::Crate, expand)
+}
+
+/// Like `parse_source`, but for snippets as well. The whole text has to be
+/// one fragment; only crates are expanded.
+pub(crate) fn parse_fragment(name: &str, source: &str, fragment: Fragment, expand: bool) -> ParsedSource {
+    parse_with(|sess| {
+        if fragment == Fragment::Crate {
+            let krate = parse::parse_crate_from_source_str(name.to_owned(), source.to_owned(), sess)?;
+            return Ok(Some(ParsedFragment::Crate(krate)));
+        }
+
+        let mut parser = parse::new_parser_from_source_str(sess, name.to_owned(), source.to_owned());
+        let parsed = match fragment {
+            Fragment::Expr => Some(ParsedFragment::Expr(parser.parse_expr()?)),
+            Fragment::Item => parser.parse_item()?.map(ParsedFragment::Item),
+            Fragment::Stmt => {
+                let stmt = parser.parse_stmt()?;
+                parser.eat(&Token::Semi);
+                stmt.map(ParsedFragment::Stmt)
+            }
+            Fragment::Crate => unreachable!(),
+        };
+        if parsed.is_none() {
+            let message = format!("expected {}", match fragment {
+                Fragment::Item => "an item",
+                _ => "a statement",
+            });
+            return Err(parser.fatal(&message));
+        }
+        parser.expect(&Token::Eof)?;
+        Ok(parsed)
+    }, expand)
+}
+
+fn parse_with<F>(parse: F, expand: bool) -> ParsedSource
+    where F: for<'a> FnOnce(&'a ParseSess) -> PResult<'a, Option<ParsedFragment>>
+{
+    let diagnostics = Rc::new(RefCell::new(vec![]));
+    let emitter = CollectingEmitter { diagnostics: diagnostics.clone() };
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+    let codemap = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    let parse_session = ParseSess::with_span_handler(handler, codemap);
+
+    // Lexer errors like an unterminated string abort the parse with a panic.
+    let parsed = catch_fatal(|| match parse(&parse_session) {
+        Ok(parsed) => parsed,
+        Err(mut e) => {
+            e.emit();
+            None
+        }
+    });
+    let parsed = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            if !parse_session.span_diagnostic.has_errors() {
+                diagnostics.borrow_mut().push(Diagnostic {
+                    level: "error".to_owned(),
+                    message: "parsing aborted on a fatal error".to_owned(),
+                    spans: vec![],
+                    notes: vec![],
+                });
+            }
+            None
+        }
+    };
+
+    // Expanding a crate the parser gave up on only piles up more errors.
+    let expand = expand && !parse_session.span_diagnostic.has_errors();
+    let parsed = match parsed {
+        Some(ParsedFragment::Crate(krate)) => {
+            Some(ParsedFragment::Crate(if expand { expand_crate(&parse_session, krate) } else { krate }))
+        }
+        parsed => parsed,
+    };
+
+    let mut vis = TreeVisitor::new(parse_session.codemap());
+    match parsed {
+        Some(ParsedFragment::Crate(ref krate)) => vis.visit_crate(krate),
+        Some(ParsedFragment::Expr(ref expr)) => vis.visit_expr(expr),
+        Some(ParsedFragment::Item(ref item)) => vis.visit_item(item),
+        Some(ParsedFragment::Stmt(ref stmt)) => vis.visit_stmt(stmt),
+        None => {}
+    }
+
+    let root_filemap = parse_session.codemap().files().first().cloned();
+    let tokens = match root_filemap {
+        Some(filemap) => tokens::lex_filemap(&filemap),
+        None => vec![],
+    };
+
+    let mut nodes = vis.roots;
+    attach_comments(&mut nodes, &tokens);
+
+    let diagnostics = diagnostics.borrow().clone();
+    let has_errors = parse_session.span_diagnostic.has_errors() || diagnostics.iter().any(|d| d.level == "error");
+    ParsedSource {
+        nodes: nodes,
+        diagnostics: diagnostics,
+        has_errors: has_errors,
+        positions: PositionMap::from_codemap(parse_session.codemap()),
+        tokens: tokens,
+    }
+}
@@ -0,0 +1,314 @@
+use std::rc::Rc;
+use syntex_syntax::codemap::CodeMap;
+use syntex_pos::FileMap;
+
+/// Line and multibyte-char tables of one source file, copied out of its
+/// `FileMap` so they outlive the `ParseSess`.
+///
+/// Codemap positions are global byte positions, while GTK text buffers
+/// count characters from the start of the buffer; this converts between them.
+#[derive(Clone, Debug)]
+pub(crate) struct SourceFile {
+    pub name: String,
+    pub start_pos: u32,
+    pub end_pos: u32,
+    /// The file's text, unless it was loaded without any.
+    pub src: Option<Rc<String>>,
+    /// Byte offsets of line starts, relative to `start_pos`.
+    lines: Vec<u32>,
+    multibyte_chars: Vec<MultiByteChar>,
+}
+
+#[derive(Clone, Debug)]
+struct MultiByteChar {
+    /// Offsets of the char, relative to `start_pos`.
+    byte_offset: u32,
+    char_offset: u32,
+    /// Extra bytes taken by this and all preceding multibyte chars.
+    extra_bytes: u32,
+}
+
+impl SourceFile {
+    fn from_filemap(filemap: &FileMap) -> SourceFile {
+        let start_pos = filemap.start_pos.0;
+        let lines = filemap.lines.borrow().iter()
+            .map(|pos| pos.0 - start_pos)
+            .collect();
+
+        let mut extra_bytes = 0;
+        let multibyte_chars = filemap.multibyte_chars.borrow().iter()
+            .map(|mbc| {
+                let byte_offset = mbc.pos.0 - start_pos;
+                let char_offset = byte_offset - extra_bytes;
+                extra_bytes += mbc.bytes as u32 - 1;
+                MultiByteChar {
+                    byte_offset: byte_offset,
+                    char_offset: char_offset,
+                    extra_bytes: extra_bytes,
+                }
+            })
+            .collect();
+
+        SourceFile {
+            name: filemap.name.clone(),
+            start_pos: start_pos,
+            end_pos: filemap.end_pos.0,
+            src: filemap.src.clone(),
+            lines: lines,
+            multibyte_chars: multibyte_chars,
+        }
+    }
+
+    pub fn contains(&self, pos: u32) -> bool {
+        self.start_pos <= pos && pos <= self.end_pos
+    }
+
+    /// Byte offset of a codemap position from the start of this file.
+    pub fn byte_offset(&self, pos: u32) -> u32 {
+        pos.saturating_sub(self.start_pos)
+    }
+
+    /// Extra bytes taken by the multibyte chars before the `idx`-th one.
+    fn extra_bytes_before(&self, idx: usize) -> u32 {
+        if idx == 0 { 0 } else { self.multibyte_chars[idx - 1].extra_bytes }
+    }
+
+    /// Character offset of a codemap position from the start of this file.
+    pub fn char_offset(&self, pos: u32) -> u32 {
+        let offset = self.byte_offset(pos);
+        let idx = match self.multibyte_chars.binary_search_by_key(&offset, |mbc| mbc.byte_offset) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        offset - self.extra_bytes_before(idx)
+    }
+
+    /// Codemap position of the character at `char_offset` in this file.
+    pub fn byte_pos(&self, char_offset: u32) -> u32 {
+        let idx = match self.multibyte_chars.binary_search_by_key(&char_offset, |mbc| mbc.char_offset) {
+            Ok(idx) | Err(idx) => idx,
+        };
+        self.start_pos + char_offset + self.extra_bytes_before(idx)
+    }
+
+    /// 1-based line and column (in chars) of a codemap position.
+    pub fn line_col(&self, pos: u32) -> (u32, u32) {
+        let offset = self.byte_offset(pos);
+        let line = match self.lines.binary_search(&offset) {
+            Ok(line) => line,
+            Err(0) => 0,
+            Err(line) => line - 1,
+        };
+        let line_start = self.lines.get(line).cloned().unwrap_or(0);
+        let col = self.char_offset(pos) - self.char_offset(self.start_pos + line_start);
+        (line as u32 + 1, col + 1)
+    }
+}
+
+/// How spans are displayed in the tree and the properties pane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SpanFormat {
+    /// `[lo..hi)` byte offsets from the start of the file.
+    Bytes,
+    /// `line:col–line:col`, 1-based, columns counted in chars.
+    LineCol,
+    /// Length of the span in chars.
+    Chars,
+}
+
+/// Position tables for every file loaded into the codemap during a parse.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PositionMap {
+    files: Vec<SourceFile>,
+}
+
+impl PositionMap {
+    pub fn from_codemap(codemap: &CodeMap) -> PositionMap {
+        PositionMap {
+            files: codemap.files().iter()
+                .map(|filemap| SourceFile::from_filemap(filemap))
+                .collect(),
+        }
+    }
+
+    /// The file that was parsed, as opposed to out-of-line modules it loaded.
+    pub fn root(&self) -> Option<&SourceFile> {
+        self.files.first()
+    }
+
+    /// All loaded files; the root file comes first, followed by the
+    /// out-of-line modules in the order the parser loaded them.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    pub fn file_for_pos(&self, pos: u32) -> Option<&SourceFile> {
+        self.files.iter().find(|file| file.contains(pos))
+    }
+
+    pub fn file_index_for_pos(&self, pos: u32) -> Option<usize> {
+        self.files.iter().position(|file| file.contains(pos))
+    }
+
+    /// Character offsets of a span within the `file`-th file, which is what
+    /// a buffer showing that file holds. `None` if the span lies elsewhere.
+    pub fn file_char_span(&self, file: usize, span: (u32, u32)) -> Option<(u32, u32)> {
+        let file = self.files.get(file)?;
+        if file.contains(span.0) && file.contains(span.1) {
+            Some((file.char_offset(span.0), file.char_offset(span.1)))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Codemap position of a character offset in the `file`-th file.
+    pub fn file_byte_pos(&self, file: usize, char_offset: u32) -> Option<u32> {
+        self.files.get(file).map(|file| file.byte_pos(char_offset))
+    }
+
+    pub fn format_span(&self, span: (u32, u32), format: SpanFormat) -> String {
+        let file = match self.file_for_pos(span.0) {
+            Some(file) => file,
+            None => return format!("[{}..{})", span.0, span.1),
+        };
+
+        match format {
+            SpanFormat::Bytes => {
+                format!("[{}..{})", file.byte_offset(span.0), file.byte_offset(span.1))
+            }
+            SpanFormat::LineCol => {
+                let (lo_line, lo_col) = file.line_col(span.0);
+                let (hi_line, hi_col) = file.line_col(span.1);
+                format!("{}:{}–{}:{}", lo_line, lo_col, hi_line, hi_col)
+            }
+            SpanFormat::Chars => {
+                // Synthesized and expanded spans can end before they start.
+                format!("{} chars", file.char_offset(span.1).saturating_sub(file.char_offset(span.0)))
+            }
+        }
+    }
+}
+
+/// Where byte `offset` of `old` ends up in `new`, taking the text between
+/// their common prefix and suffix as replaced. The prefix is cut back to a
+/// line start, so that a line inserted in front of a similar one doesn't
+/// shift it. `None` if the offset lay in the replaced text.
+pub(crate) fn offset_after_edit(old: &str, new: &str, offset: u32) -> Option<u32> {
+    let prefix = old.bytes().zip(new.bytes()).take_while(|&(a, b)| a == b).count();
+    let prefix = old.as_bytes()[..prefix].iter().rposition(|&b| b == b'\n').map_or(0, |newline| newline + 1);
+    let suffix = old.bytes().rev().zip(new.bytes().rev())
+        .take(old.len().min(new.len()) - prefix)
+        .take_while(|&(a, b)| a == b)
+        .count();
+
+    let offset = offset as usize;
+    if offset < prefix {
+        Some(offset as u32)
+    }
+    else if offset >= old.len() - suffix {
+        Some((offset + new.len() - old.len()) as u32)
+    }
+    else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+    use super::{offset_after_edit, MultiByteChar, PositionMap, SourceFile, SpanFormat};
+
+    /// The tables the lexer would record for `src` loaded at `start_pos`.
+    fn source_file(start_pos: u32, src: &str) -> SourceFile {
+        let mut lines = vec![0];
+        let mut multibyte_chars = vec![];
+        let mut extra_bytes = 0;
+        for (char_offset, (byte_offset, c)) in src.char_indices().enumerate() {
+            if c == '\n' {
+                lines.push(byte_offset as u32 + 1);
+            }
+            if c.len_utf8() > 1 {
+                extra_bytes += c.len_utf8() as u32 - 1;
+                multibyte_chars.push(MultiByteChar {
+                    byte_offset: byte_offset as u32,
+                    char_offset: char_offset as u32,
+                    extra_bytes: extra_bytes,
+                });
+            }
+        }
+        SourceFile {
+            name: "test.rs".to_owned(),
+            start_pos: start_pos,
+            end_pos: start_pos + src.len() as u32,
+            src: Some(Rc::new(src.to_owned())),
+            lines: lines,
+            multibyte_chars: multibyte_chars,
+        }
+    }
+
+    #[test]
+    fn converts_between_bytes_and_chars() {
+        // Bytes: a 0, é 1-2, \n 3, ü 4-5, b 6.
+        let file = source_file(10, "aé\nüb");
+        assert_eq!(file.char_offset(10), 0);
+        assert_eq!(file.char_offset(11), 1);
+        assert_eq!(file.char_offset(13), 2);
+        assert_eq!(file.char_offset(14), 3);
+        assert_eq!(file.char_offset(16), 4);
+        assert_eq!(file.char_offset(17), 5);
+
+        for char_offset in 0..6 {
+            assert_eq!(file.char_offset(file.byte_pos(char_offset)), char_offset);
+        }
+        assert_eq!(file.byte_pos(2), 13);
+        assert_eq!(file.byte_pos(3), 14);
+        assert_eq!(file.byte_pos(4), 16);
+    }
+
+    #[test]
+    fn counts_columns_in_chars() {
+        let file = source_file(10, "aé\nüb");
+        assert_eq!(file.line_col(10), (1, 1));
+        assert_eq!(file.line_col(13), (1, 3));
+        assert_eq!(file.line_col(14), (2, 1));
+        assert_eq!(file.line_col(16), (2, 2));
+    }
+
+    #[test]
+    fn maps_spans_within_one_file() {
+        let positions = PositionMap { files: vec![source_file(10, "aé\nüb"), source_file(18, "x")] };
+        assert_eq!(positions.file_char_span(0, (11, 16)), Some((1, 4)));
+        assert_eq!(positions.file_char_span(0, (18, 19)), None);
+        assert_eq!(positions.file_char_span(1, (18, 19)), Some((0, 1)));
+        assert_eq!(positions.file_index_for_pos(18), Some(1));
+        assert_eq!(positions.file_byte_pos(0, 4), Some(16));
+    }
+
+    #[test]
+    fn formats_spans() {
+        let positions = PositionMap { files: vec![source_file(10, "aé\nüb")] };
+        assert_eq!(positions.format_span((11, 16), SpanFormat::Bytes), "[1..6)");
+        assert_eq!(positions.format_span((11, 16), SpanFormat::LineCol), "1:2–2:2");
+        assert_eq!(positions.format_span((11, 16), SpanFormat::Chars), "3 chars");
+        assert_eq!(positions.format_span((16, 11), SpanFormat::Chars), "0 chars");
+    }
+
+    #[test]
+    fn maps_offsets_through_an_edit() {
+        let old = "fn a() {}\nfn b() {}\n";
+        // A similar line inserted in front of `fn b` moves it.
+        let new = "fn a() {}\nfn x() {}\nfn b() {}\n";
+        assert_eq!(offset_after_edit(old, new, 0), Some(0));
+        assert_eq!(offset_after_edit(old, new, 10), Some(20));
+        assert_eq!(offset_after_edit(old, new, 19), Some(29));
+
+        // Offsets in removed text are gone.
+        let new = "fn b() {}\n";
+        assert_eq!(offset_after_edit(old, new, 0), None);
+        assert_eq!(offset_after_edit(old, new, 10), Some(0));
+
+        assert_eq!(offset_after_edit(old, old, 13), Some(13));
+        assert_eq!(offset_after_edit(old, "", 10), None);
+        assert_eq!(offset_after_edit("é", "è", 2), Some(2));
+    }
+}
@@ -0,0 +1,321 @@
+use echain::{Error, ErrorKind, Result};
+use ast_node::AstNode;
+
+/// How a step relates to the step before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Combinator {
+    /// `A B`: B anywhere below A.
+    Descendant,
+    /// `A > B`: B directly below A.
+    Child,
+}
+
+/// `[key]` or `[key=value]`.
+#[derive(Clone, Debug)]
+struct AttributeSelector {
+    key: String,
+    value: Option<String>,
+}
+
+impl AttributeSelector {
+    /// `type` and `kind` test the node itself, `name` its Name or Ident
+    /// property, and any other key the property of that name.
+    fn matches(&self, node: &AstNode) -> bool {
+        let key = self.key.to_lowercase();
+        let values: Vec<&str> = match key.as_str() {
+            "type" => vec![node.ty.as_str()],
+            "kind" => vec![node.kind.as_str()],
+            "name" => node.properties.iter()
+                .filter(|property| property.name == "Name" || property.name == "Ident")
+                .map(|property| property.value.as_str())
+                .collect(),
+            _ => node.properties.iter()
+                .filter(|property| property.name.to_lowercase() == key)
+                .map(|property| property.value.as_str())
+                .collect(),
+        };
+
+        match self.value {
+            Some(ref value) => values.iter().any(|v| *v == value.as_str()),
+            None => values.iter().any(|v| !v.is_empty()),
+        }
+    }
+}
+
+/// One compound selector, e.g. `Expr[kind=MethodCall]`.
+#[derive(Clone, Debug)]
+struct Step {
+    combinator: Combinator,
+    /// `None` for `*` or a selector made of attributes only.
+    ty: Option<String>,
+    attributes: Vec<AttributeSelector>,
+}
+
+impl Step {
+    fn matches(&self, node: &AstNode) -> bool {
+        self.ty.as_ref().map_or(true, |ty| *ty == node.ty)
+            && self.attributes.iter().all(|attribute| attribute.matches(node))
+    }
+}
+
+/// A node that matched a query.
+pub(crate) struct QueryMatch<'a> {
+    /// Child indices leading from the roots to the node, which is also its
+    /// path in the syntax tree store.
+    pub path: Vec<usize>,
+    pub node: &'a AstNode,
+}
+
+/// A selector over the syntax tree, in the spirit of CSS selectors:
+///
+/// ```text
+/// Item[kind=Fn] > Block Expr[kind=MethodCall][name=unwrap]
+/// ```
+///
+/// finds every `unwrap` call inside a function body. Steps are separated by
+/// whitespace (any descendant) or `>` (direct child); each names a node
+/// Type, or `*`, followed by any number of `[key]` / `[key=value]`
+/// attributes. Values containing spaces or brackets can be quoted.
+#[derive(Clone, Debug)]
+pub(crate) struct Query {
+    steps: Vec<Step>,
+}
+
+impl Query {
+    pub fn parse(source: &str) -> Result<Query> {
+        QueryParser { chars: source.chars().collect(), pos: 0 }.query()
+    }
+
+    /// Every matching node, in tree order.
+    pub fn find_all<'a>(&self, nodes: &'a [AstNode]) -> Vec<QueryMatch<'a>> {
+        let mut matches = vec![];
+        let mut ancestors = vec![];
+        let mut path = vec![];
+        self.find_in(nodes, &mut ancestors, &mut path, &mut matches);
+        matches
+    }
+
+    fn find_in<'a>(&self, nodes: &'a [AstNode], ancestors: &mut Vec<&'a AstNode>, path: &mut Vec<usize>,
+                   matches: &mut Vec<QueryMatch<'a>>) {
+        for (index, node) in nodes.iter().enumerate() {
+            ancestors.push(node);
+            path.push(index);
+            if self.matches_at(self.steps.len() - 1, ancestors) {
+                matches.push(QueryMatch { path: path.clone(), node: node });
+            }
+            self.find_in(&node.children, ancestors, path, matches);
+            path.pop();
+            ancestors.pop();
+        }
+    }
+
+    /// Whether the last node of `ancestors` matches `steps[..step + 1]`.
+    fn matches_at(&self, step: usize, ancestors: &[&AstNode]) -> bool {
+        let (node, rest) = match ancestors.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        if !self.steps[step].matches(node) {
+            return false;
+        }
+        if step == 0 {
+            return true;
+        }
+
+        match self.steps[step].combinator {
+            Combinator::Child => self.matches_at(step - 1, rest),
+            Combinator::Descendant => (1..rest.len() + 1).rev()
+                .any(|len| self.matches_at(step - 1, &rest[..len])),
+        }
+    }
+}
+
+struct QueryParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn error(&self, message: &str) -> Error {
+        ErrorKind::InvalidQuery(format!("{} at column {}", message, self.pos + 1)).into()
+    }
+
+    /// Returns whether there was any whitespace.
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected `{}`", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn word(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().map_or(false, |c| c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// A bare word up to the closing `]`, or a `"quoted string"`.
+    fn value(&mut self) -> Result<String> {
+        let start = self.pos;
+        if self.peek() == Some('"') {
+            self.pos += 1;
+            while self.peek().map_or(false, |c| c != '"') {
+                self.pos += 1;
+            }
+            self.expect('"')?;
+            return Ok(self.chars[start + 1..self.pos - 1].iter().collect());
+        }
+
+        while self.peek().map_or(false, |c| c != ']' && !c.is_whitespace()) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("expected a value"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn attribute(&mut self) -> Result<AttributeSelector> {
+        self.expect('[')?;
+        self.skip_whitespace();
+        let key = self.word();
+        if key.is_empty() {
+            return Err(self.error("expected an attribute name"));
+        }
+        self.skip_whitespace();
+        let value = if self.peek() == Some('=') {
+            self.pos += 1;
+            self.skip_whitespace();
+            Some(self.value()?)
+        }
+        else {
+            None
+        };
+        self.skip_whitespace();
+        self.expect(']')?;
+        Ok(AttributeSelector { key: key, value: value })
+    }
+
+    fn step(&mut self, combinator: Combinator) -> Result<Step> {
+        let any = self.peek() == Some('*');
+        let ty = if any {
+            self.pos += 1;
+            None
+        }
+        else {
+            let word = self.word();
+            if word.is_empty() { None } else { Some(word) }
+        };
+
+        let mut attributes = vec![];
+        while self.peek() == Some('[') {
+            attributes.push(self.attribute()?);
+        }
+        if !any && ty.is_none() && attributes.is_empty() {
+            return Err(self.error("expected a node type, `*` or `[`"));
+        }
+        Ok(Step { combinator: combinator, ty: ty, attributes: attributes })
+    }
+
+    fn query(&mut self) -> Result<Query> {
+        self.skip_whitespace();
+        let mut steps = vec![self.step(Combinator::Descendant)?];
+        loop {
+            let spaced = self.skip_whitespace();
+            match self.peek() {
+                None => break,
+                Some('>') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                    steps.push(self.step(Combinator::Child)?);
+                }
+                Some(_) if spaced => steps.push(self.step(Combinator::Descendant)?),
+                Some(c) => return Err(self.error(&format!("unexpected `{}`", c))),
+            }
+        }
+        Ok(Query { steps: steps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast_node::{AstNode, Property};
+    use super::Query;
+
+    fn node(ty: &str, kind: &str, span: (u32, u32), children: Vec<AstNode>) -> AstNode {
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            span: Some(span),
+            children: children,
+            ..AstNode::default()
+        }
+    }
+
+    fn named(mut node: AstNode, name: &str) -> AstNode {
+        node.add_property(Property::new("Name", name));
+        node
+    }
+
+    /// `fn main() { a.unwrap(); b.len(); }`, roughly.
+    fn tree() -> Vec<AstNode> {
+        vec![
+            named(node("Item", "Fn", (0, 40), vec![
+                node("Block", "", (10, 40), vec![
+                    node("Stmt", "Semi", (12, 23), vec![
+                        named(node("Expr", "MethodCall", (12, 22), vec![]), "unwrap"),
+                    ]),
+                    node("Stmt", "Semi", (24, 32), vec![
+                        named(node("Expr", "MethodCall", (24, 31), vec![]), "len"),
+                    ]),
+                ]),
+            ]), "main"),
+        ]
+    }
+
+    fn paths(query: &str, nodes: &[AstNode]) -> Vec<Vec<usize>> {
+        Query::parse(query).unwrap().find_all(nodes).into_iter().map(|found| found.path).collect()
+    }
+
+    #[test]
+    fn matches_type_and_attributes() {
+        let nodes = tree();
+        assert_eq!(paths("Expr", &nodes), vec![vec![0, 0, 0, 0], vec![0, 0, 1, 0]]);
+        assert_eq!(paths("Expr[kind=MethodCall][name=unwrap]", &nodes), vec![vec![0, 0, 0, 0]]);
+        assert_eq!(paths("*[name=main]", &nodes), vec![vec![0]]);
+        assert_eq!(paths("[NAME=\"len\"]", &nodes), vec![vec![0, 0, 1, 0]]);
+        assert_eq!(paths("Item[name]", &nodes), vec![vec![0]]);
+        assert!(paths("Stmt[name]", &nodes).is_empty());
+    }
+
+    #[test]
+    fn combinators() {
+        let nodes = tree();
+        assert_eq!(paths("Item[kind=Fn] > Block Expr[name=len]", &nodes), vec![vec![0, 0, 1, 0]]);
+        assert_eq!(paths("Block > Stmt > Expr", &nodes).len(), 2);
+        assert!(paths("Item > Expr", &nodes).is_empty());
+        assert!(paths("Expr Stmt", &nodes).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        for query in &["", "Expr[", "Expr[kind=", "Expr[=Fn]", "Item >", "Expr]"] {
+            assert!(Query::parse(query).is_err(), "{:?} should not parse", query);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use gtk::prelude::*;
+use gtk::{TreeModel, TreeIter, TreePath, ListStore, TreeModelExt, ListStoreExtManual};
+use query::QueryMatch;
+
+pub(crate) enum QueryResultsColumns {
+    Type = 0,
+    Kind = 1,
+    Name = 2,
+    HasSpan = 3,
+    Lo = 4,
+    Hi = 5,
+    /// The node's path in the syntax tree store, as a `TreePath` string.
+    Path = 6,
+}
+
+pub(crate) trait QueryResultsModelExt {
+    fn get_result_span(&self, iter: &TreeIter) -> Option<(u32, u32)>;
+    fn get_result_path(&self, iter: &TreeIter) -> TreePath;
+}
+
+macro_rules! get_query_results_value {
+    ($model:expr, $iter:expr, $col:ident, $type:ty) => {
+        $model.get_value($iter, QueryResultsColumns::$col as i32)
+                    .get::<$type>().expect("Could not get value from ListStore")
+    }
+}
+
+impl<O: IsA<TreeModel> + TreeModelExt> QueryResultsModelExt for O {
+    fn get_result_span(&self, iter: &TreeIter) -> Option<(u32, u32)> {
+        if !get_query_results_value!(self, iter, HasSpan, bool) {
+            return None;
+        }
+        let lo = get_query_results_value!(self, iter, Lo, u32);
+        let hi = get_query_results_value!(self, iter, Hi, u32);
+        Some((lo, hi))
+    }
+
+    fn get_result_path(&self, iter: &TreeIter) -> TreePath {
+        TreePath::new_from_string(&get_query_results_value!(self, iter, Path, String))
+    }
+}
+
+pub(crate) trait QueryResultsStoreExt {
+    fn new_query_results_store() -> ListStore;
+    fn insert_result(&self, found: &QueryMatch) -> TreeIter;
+}
+
+impl<O: IsA<ListStore> + ListStoreExtManual> QueryResultsStoreExt for O {
+    fn new_query_results_store() -> ListStore {
+        ListStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            bool::static_type(),
+            u32::static_type(),
+            u32::static_type(),
+            String::static_type()
+        ])
+    }
+
+    fn insert_result(&self, found: &QueryMatch) -> TreeIter {
+        let node = found.node;
+        let name = node.properties.iter()
+            .find(|property| property.name == "Name" || property.name == "Ident")
+            .map(|property| property.value.clone())
+            .unwrap_or_default();
+        let has_span = node.span.is_some();
+        let (lo, hi) = node.span.unwrap_or((0, 0));
+        let path = found.path.iter()
+            .map(|index| index.to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let cols: Vec<u32> = vec![
+            QueryResultsColumns::Type as u32,
+            QueryResultsColumns::Kind as u32,
+            QueryResultsColumns::Name as u32,
+            QueryResultsColumns::HasSpan as u32,
+            QueryResultsColumns::Lo as u32,
+            QueryResultsColumns::Hi as u32,
+            QueryResultsColumns::Path as u32
+        ];
+        let vals: Vec<&ToValue> = vec![
+            &node.ty,
+            &node.kind,
+            &name,
+            &has_span,
+            &lo,
+            &hi,
+            &path
+        ];
+
+        self.insert_with_values(None, &cols, &vals)
+    }
+}
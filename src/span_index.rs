@@ -0,0 +1,201 @@
+use std::cmp::Reverse;
+use ast_node::AstNode;
+
+/// Where a node sits in the tree, so its path can be rebuilt from its id.
+struct IndexedNode {
+    parent: Option<usize>,
+    /// Position among its parent's children, or among the roots.
+    child_index: usize,
+}
+
+/// Answers "which is the smallest node containing this position" with a
+/// binary search, instead of walking the whole tree.
+///
+/// The distinct span boundaries split the file into slots: one for each
+/// boundary and one for the gap after it. Every slot remembers the smallest
+/// node covering it, so a lookup only has to find the slot.
+#[derive(Default)]
+pub(crate) struct SpanIndex {
+    /// Sorted, distinct `lo` and `hi` positions of all spans.
+    bounds: Vec<u32>,
+    /// Slot `2 * i` is `bounds[i]` itself, slot `2 * i + 1` lies strictly
+    /// between `bounds[i]` and `bounds[i + 1]`.
+    slots: Vec<Option<usize>>,
+    nodes: Vec<IndexedNode>,
+}
+
+/// Finds the next slot at or after `slot` that hasn't been claimed yet,
+/// compressing the path as it goes.
+fn next_free(next: &mut Vec<usize>, slot: usize) -> usize {
+    let mut root = slot;
+    while next[root] != root {
+        root = next[root];
+    }
+    let mut slot = slot;
+    while next[slot] != root {
+        let following = next[slot];
+        next[slot] = root;
+        slot = following;
+    }
+    root
+}
+
+impl SpanIndex {
+    pub fn new(roots: &[AstNode]) -> SpanIndex {
+        let mut nodes = vec![];
+        // (span, depth, id) of every node that has a span.
+        let mut spans = vec![];
+        SpanIndex::flatten(roots, None, 0, &mut nodes, &mut spans);
+
+        let mut bounds = Vec::with_capacity(spans.len() * 2);
+        for &((lo, hi), _, _) in &spans {
+            bounds.push(lo);
+            bounds.push(hi);
+        }
+        bounds.sort();
+        bounds.dedup();
+
+        // Smallest spans claim their slots first; on equal spans the deeper
+        // node wins, like it does in the tree.
+        spans.sort_by_key(|&((lo, hi), depth, _)| (hi.saturating_sub(lo), Reverse(depth)));
+
+        let slot_count = bounds.len() * 2;
+        let mut slots = vec![None; slot_count];
+        // `next[slot]` points towards the first unclaimed slot from there on;
+        // `slot_count` is a sentinel.
+        let mut next: Vec<usize> = (0..slot_count + 1).collect();
+        for &((lo, hi), _, id) in &spans {
+            let first = 2 * bounds.binary_search(&lo).expect("Span bound missing from index");
+            let last = 2 * bounds.binary_search(&hi).expect("Span bound missing from index");
+            let mut slot = next_free(&mut next, first);
+            while slot <= last {
+                slots[slot] = Some(id);
+                next[slot] = slot + 1;
+                slot = next_free(&mut next, slot + 1);
+            }
+        }
+
+        SpanIndex { bounds: bounds, slots: slots, nodes: nodes }
+    }
+
+    fn flatten(children: &[AstNode], parent: Option<usize>, depth: usize,
+               nodes: &mut Vec<IndexedNode>, spans: &mut Vec<((u32, u32), usize, usize)>) {
+        for (child_index, node) in children.iter().enumerate() {
+            let id = nodes.len();
+            nodes.push(IndexedNode { parent: parent, child_index: child_index });
+            if let Some(span) = node.span {
+                spans.push((span, depth, id));
+            }
+            SpanIndex::flatten(&node.children, Some(id), depth + 1, nodes, spans);
+        }
+    }
+
+    /// Child indices leading to the smallest node whose span contains `pos`,
+    /// ends included.
+    pub fn find(&self, pos: u32) -> Option<Vec<usize>> {
+        let slot = match self.bounds.binary_search(&pos) {
+            Ok(i) => 2 * i,
+            Err(i) if i > 0 && i < self.bounds.len() => 2 * (i - 1) + 1,
+            Err(_) => return None,
+        };
+        self.slots[slot].map(|id| self.path(id))
+    }
+
+    fn path(&self, id: usize) -> Vec<usize> {
+        let mut path = vec![];
+        let mut current = Some(id);
+        while let Some(id) = current {
+            path.push(self.nodes[id].child_index);
+            current = self.nodes[id].parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ast_node::AstNode;
+    use super::SpanIndex;
+
+    fn node(ty: &str, kind: &str, span: Option<(u32, u32)>, children: Vec<AstNode>) -> AstNode {
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            span: span,
+            children: children,
+            ..AstNode::default()
+        }
+    }
+
+    #[test]
+    fn finds_innermost_node() {
+        let roots = vec![
+            node("Item", "Fn", Some((0, 20)), vec![
+                node("Expr", "Call", Some((2, 8)), vec![
+                    node("Expr", "Path", Some((4, 6)), vec![]),
+                ]),
+                node("Block", "", Some((10, 18)), vec![]),
+            ]),
+        ];
+        let index = SpanIndex::new(&roots);
+        assert_eq!(index.find(0), Some(vec![0]));
+        assert_eq!(index.find(3), Some(vec![0, 0]));
+        assert_eq!(index.find(5), Some(vec![0, 0, 0]));
+        assert_eq!(index.find(6), Some(vec![0, 0, 0]));
+        assert_eq!(index.find(8), Some(vec![0, 0]));
+        assert_eq!(index.find(9), Some(vec![0]));
+        assert_eq!(index.find(12), Some(vec![0, 1]));
+        assert_eq!(index.find(20), Some(vec![0]));
+        assert_eq!(index.find(21), None);
+    }
+
+    #[test]
+    fn deeper_node_wins_on_equal_spans() {
+        let roots = vec![
+            node("Stmt", "Semi", Some((0, 5)), vec![
+                node("Expr", "Call", Some((0, 5)), vec![]),
+            ]),
+        ];
+        let index = SpanIndex::new(&roots);
+        assert_eq!(index.find(0), Some(vec![0, 0]));
+        assert_eq!(index.find(2), Some(vec![0, 0]));
+        assert_eq!(index.find(5), Some(vec![0, 0]));
+    }
+
+    #[test]
+    fn searches_all_roots_and_skips_nodes_without_span() {
+        let roots = vec![
+            node("Mod", "", None, vec![
+                node("Item", "Fn", Some((3, 7)), vec![]),
+            ]),
+            node("Item", "Struct", Some((10, 12)), vec![]),
+        ];
+        let index = SpanIndex::new(&roots);
+        assert_eq!(index.find(5), Some(vec![0, 0]));
+        assert_eq!(index.find(11), Some(vec![1]));
+        assert_eq!(index.find(8), None);
+        assert_eq!(index.find(1), None);
+    }
+
+    #[test]
+    fn empty_index_finds_nothing() {
+        assert_eq!(SpanIndex::new(&[]).find(0), None);
+        assert_eq!(SpanIndex::default().find(0), None);
+    }
+
+    #[test]
+    fn finds_nodes_outside_their_parents_span() {
+        // A leading comment is a child of the item it precedes.
+        let roots = vec![
+            node("Item", "Fn", Some((10, 30)), vec![
+                node("Comment", "Leading", Some((0, 8)), vec![]),
+                node("Block", "", Some((20, 30)), vec![]),
+            ]),
+        ];
+        let index = SpanIndex::new(&roots);
+        assert_eq!(index.find(4), Some(vec![0, 0]));
+        assert_eq!(index.find(9), None);
+        assert_eq!(index.find(25), Some(vec![0, 1]));
+    }
+}
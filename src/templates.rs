@@ -0,0 +1,47 @@
+/// Starting points for a scratchpad, as (menu label, code).
+pub(crate) const TEMPLATES: &[(&str, &str)] = &[
+    ("_Function", FUNCTION),
+    ("_Impl Block", IMPL_BLOCK),
+    ("_Trait", TRAIT),
+];
+
+const FUNCTION: &str = "\
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+";
+
+const IMPL_BLOCK: &str = "\
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+impl Point {
+    fn new(x: f64, y: f64) -> Point {
+        Point { x: x, y: y }
+    }
+
+    fn length(&self) -> f64 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+}
+";
+
+const TRAIT: &str = "\
+trait Shape {
+    fn area(&self) -> f64;
+
+    fn describe(&self) -> String {
+        format!(\"area {}\", self.area())
+    }
+}
+
+struct Square(f64);
+
+impl Shape for Square {
+    fn area(&self) -> f64 {
+        self.0 * self.0
+    }
+}
+";
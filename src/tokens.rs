@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use syntex_errors::Handler;
+use syntex_pos::{FileMap, Span};
+use syntex_syntax::codemap::{CodeMap, FilePathMapping};
+use syntex_syntax::parse::ParseSess;
+use syntex_syntax::parse::lexer::StringReader;
+use syntex_syntax::parse::token::Token;
+use syntex_syntax::print::pprust;
+
+use diagnostics::{catch_fatal, CollectingEmitter};
+
+/// Lexer output the parser skips over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Trivia {
+    Whitespace,
+    Comment,
+}
+
+impl fmt::Display for Trivia {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A raw token as produced by `StringReader`, trivia included.
+#[derive(Clone, Debug)]
+pub(crate) struct SourceToken {
+    pub kind: String,
+    /// The token as written in the source.
+    pub text: String,
+    pub span: (u32, u32),
+    pub trivia: Option<Trivia>,
+}
+
+impl SourceToken {
+    fn new(codemap: &CodeMap, token: Token, span: Span) -> SourceToken {
+        let trivia = match token {
+            Token::Whitespace => Some(Trivia::Whitespace),
+            Token::Comment | Token::Shebang(_) => Some(Trivia::Comment),
+            _ => None,
+        };
+        SourceToken {
+            kind: token_kind(&token),
+            text: codemap.span_to_snippet(span).unwrap_or_else(|_| pprust::token_to_string(&token)),
+            span: (span.lo.0, span.hi.0),
+            trivia: trivia,
+        }
+    }
+}
+
+/// Variant name of a token, e.g. `Ident` for `Ident(foo)`.
+pub(crate) fn token_kind(token: &Token) -> String {
+    let debug = format!("{:?}", token);
+    debug.split('(').next().unwrap_or("").to_owned()
+}
+
+/// Lexes the source of a file the parser has loaded. Spans are positions
+/// in the parser's codemap; on a fatal lexer error the tokens up to it are
+/// returned.
+pub(crate) fn lex_filemap(filemap: &FileMap) -> Vec<SourceToken> {
+    let src = match filemap.src {
+        Some(ref src) => (**src).clone(),
+        None => return vec![],
+    };
+    // Lexing records line starts in the file map, so lex a copy of the file
+    // rather than the parser's already lexed one.
+    let codemap = Rc::new(CodeMap::new(FilePathMapping::empty()));
+    let copy = codemap.new_filemap(filemap.name.clone(), src);
+    let offset = filemap.start_pos.0 - copy.start_pos.0;
+
+    // Lexer errors were already reported by the parser; don't collect them twice.
+    let emitter = CollectingEmitter { diagnostics: Rc::new(RefCell::new(vec![])) };
+    let handler = Handler::with_emitter(true, false, Box::new(emitter));
+    let sess = ParseSess::with_span_handler(handler, codemap.clone());
+
+    let mut tokens = vec![];
+    catch_fatal(|| {
+        let mut reader = StringReader::new(&sess, copy);
+        while let Ok(token) = reader.try_next_token() {
+            if token.tok == Token::Eof {
+                break;
+            }
+            let mut source_token = SourceToken::new(&codemap, token.tok, token.sp);
+            source_token.span = (source_token.span.0 + offset, source_token.span.1 + offset);
+            tokens.push(source_token);
+        }
+    });
+    tokens
+}
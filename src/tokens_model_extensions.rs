@@ -0,0 +1,70 @@
+use gtk::prelude::*;
+use gtk::{TreeModel, TreeIter, ListStore, TreeModelExt, ListStoreExtManual};
+use tokens::SourceToken;
+
+pub(crate) enum TokensColumns {
+    Kind = 0,
+    Text = 1,
+    Trivia = 2,
+    Lo = 3,
+    Hi = 4,
+}
+
+pub(crate) trait TokensModelExt {
+    fn get_token_span(&self, iter: &TreeIter) -> (u32, u32);
+}
+
+macro_rules! get_tokens_model_value {
+    ($model:expr, $iter:expr, $col:ident, $type:ty) => {
+        $model.get_value($iter, TokensColumns::$col as i32)
+                    .get::<$type>().expect("Could not get value from ListStore")
+    }
+}
+
+impl<O: IsA<TreeModel> + TreeModelExt> TokensModelExt for O {
+    fn get_token_span(&self, iter: &TreeIter) -> (u32, u32) {
+        let lo = get_tokens_model_value!(self, iter, Lo, u32);
+        let hi = get_tokens_model_value!(self, iter, Hi, u32);
+        (lo, hi)
+    }
+}
+
+pub(crate) trait TokensStoreExt {
+    fn new_tokens_store() -> ListStore;
+    fn insert_token(&self, token: &SourceToken) -> TreeIter;
+}
+
+impl<O: IsA<ListStore> + ListStoreExtManual> TokensStoreExt for O {
+    fn new_tokens_store() -> ListStore {
+        ListStore::new(&[
+            String::static_type(),
+            String::static_type(),
+            String::static_type(),
+            u32::static_type(),
+            u32::static_type()
+        ])
+    }
+
+    fn insert_token(&self, token: &SourceToken) -> TreeIter {
+        let cols: Vec<u32> = vec![
+            TokensColumns::Kind as u32,
+            TokensColumns::Text as u32,
+            TokensColumns::Trivia as u32,
+            TokensColumns::Lo as u32,
+            TokensColumns::Hi as u32
+        ];
+
+        // Keep whitespace tokens visible in a single-line cell.
+        let text = token.text.escape_default().to_string();
+        let trivia = token.trivia.map(|t| t.to_string()).unwrap_or_default();
+        let vals: Vec<&ToValue> = vec![
+            &token.kind,
+            &text,
+            &trivia,
+            &token.span.0,
+            &token.span.1
+        ];
+
+        self.insert_with_values(None, &cols, &vals)
+    }
+}
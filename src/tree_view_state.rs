@@ -0,0 +1,88 @@
+use gtk::prelude::*;
+use gtk::{TreeView, TreeViewExt, TreeModel, TreeIter, TreePath};
+use ast_model_extensions::AstModelExt;
+
+/// A row of the syntax tree, identified by its path and Type/Kind so that it
+/// is only matched against a row describing the same node in a new model.
+struct RowKey {
+    path: TreePath,
+    ty: String,
+    kind: String,
+}
+
+impl RowKey {
+    fn new(model: &TreeModel, iter: &TreeIter) -> Option<RowKey> {
+        Some(RowKey {
+            path: model.get_path(iter)?,
+            ty: model.get_type(iter),
+            kind: model.get_kind(iter),
+        })
+    }
+
+    fn find(&self, model: &TreeModel) -> Option<TreePath> {
+        let iter = model.get_iter(&self.path)?;
+        if model.get_type(&iter) == self.ty && model.get_kind(&iter) == self.kind {
+            Some(self.path.clone())
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// Expanded rows and selection of a syntax tree view, saved before its model
+/// is replaced and restored afterwards wherever the node still exists.
+pub(crate) struct TreeViewState {
+    expanded: Vec<RowKey>,
+    selected: Option<RowKey>,
+}
+
+fn collect_expanded(view: &TreeView, model: &TreeModel, parent: Option<&TreeIter>, expanded: &mut Vec<RowKey>) {
+    let mut child = model.iter_children(parent);
+    while let Some(iter) = child {
+        if let Some(key) = RowKey::new(model, &iter) {
+            if view.row_expanded(&key.path) {
+                expanded.push(key);
+                collect_expanded(view, model, Some(&iter), expanded);
+            }
+        }
+        child = if model.iter_next(&iter) { Some(iter) } else { None };
+    }
+}
+
+impl TreeViewState {
+    pub fn save(view: &TreeView) -> TreeViewState {
+        let mut expanded = vec![];
+        let mut selected = None;
+
+        if let Some(model) = view.get_model() {
+            collect_expanded(view, &model, None, &mut expanded);
+        }
+        if let Some((model, iter)) = view.get_selection().get_selected() {
+            selected = RowKey::new(&model, &iter);
+        }
+
+        TreeViewState {
+            expanded: expanded,
+            selected: selected,
+        }
+    }
+
+    pub fn restore(&self, view: &TreeView) {
+        let model = match view.get_model() {
+            Some(model) => model,
+            None => return,
+        };
+
+        // Parents are saved before their children, so expanding in order works.
+        for key in &self.expanded {
+            if let Some(path) = key.find(&model) {
+                view.expand_row(&path, false);
+            }
+        }
+
+        if let Some(path) = self.selected.as_ref().and_then(|key| key.find(&model)) {
+            view.get_selection().select_path(&path);
+        }
+    }
+}
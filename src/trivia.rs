@@ -0,0 +1,250 @@
+use ast_node::{AstNode, Property};
+use tokens::{SourceToken, Trivia};
+
+/// Attaches the comments among `tokens` to the nodes they belong to.
+///
+/// A comment on the same line as the preceding token is trailing trivia of
+/// the innermost node ending with that token; any other comment is leading
+/// trivia of the outermost node starting with the next token, not counting
+/// the crate and module nodes around the first item. Comments with no such
+/// node go to the innermost node around them.
+///
+/// Node spans are left as parsed, so a leading comment lies before its
+/// node's span; `SpanIndex` still finds it by the comment node's own span.
+pub(crate) fn attach_comments(nodes: &mut Vec<AstNode>, tokens: &[SourceToken]) {
+    let mut previous: Option<u32> = None;
+    let mut newline_since_previous = false;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token.trivia {
+            Some(Trivia::Whitespace) => {
+                newline_since_previous |= token.text.contains('\n');
+            }
+            Some(Trivia::Comment) => {
+                let next = tokens[i + 1..].iter().find(|t| t.trivia.is_none()).map(|t| t.span.0);
+                let path = match (previous, next) {
+                    (Some(hi), _) if !newline_since_previous => {
+                        path_to_innermost(nodes, (hi, hi), &|node| node.span.map_or(false, |s| s.1 == hi))
+                            .map(|path| (path, "Trailing"))
+                    }
+                    (_, Some(lo)) => path_to_outermost_starting_at(nodes, lo).map(|path| (path, "Leading")),
+                    _ => None,
+                };
+                let path = path.or_else(|| {
+                    path_to_innermost(nodes, token.span, &|_| true).map(|path| (path, "Inner"))
+                });
+                let (path, kind) = match path {
+                    Some(found) => found,
+                    None => continue,
+                };
+
+                let comment = comment_node(token, kind);
+                let node = node_at_mut(nodes, &path);
+                // In source order, so consecutive leading comments stay in order.
+                let at = node.children.iter().position(|child| child.span.map_or(false, |s| s.0 > token.span.0))
+                    .unwrap_or(node.children.len());
+                node.children.insert(at, comment);
+            }
+            None => {
+                previous = Some(token.span.1);
+                newline_since_previous = false;
+            }
+        }
+    }
+}
+
+fn comment_node(token: &SourceToken, kind: &str) -> AstNode {
+    let style = if token.text.starts_with("/*") { "Block" } else { "Line" };
+    AstNode {
+        ty: "Comment".to_owned(),
+        kind: kind.to_owned(),
+        span: Some(token.span),
+        properties: vec![
+            Property::new("Style", style),
+            Property::new("Text", &token.text),
+        ],
+        ..AstNode::default()
+    }
+}
+
+fn contains(node: &AstNode, span: (u32, u32)) -> bool {
+    node.span.map_or(true, |(lo, hi)| lo <= span.0 && span.1 <= hi)
+}
+
+/// Child indices leading to the innermost node around `span` that satisfies `matches`.
+fn path_to_innermost(nodes: &[AstNode], span: (u32, u32), matches: &Fn(&AstNode) -> bool) -> Option<Vec<usize>> {
+    for (i, node) in nodes.iter().enumerate() {
+        if !contains(node, span) {
+            continue;
+        }
+        if let Some(mut path) = path_to_innermost(&node.children, span, matches) {
+            path.insert(0, i);
+            return Some(path);
+        }
+        if node.span.is_some() && matches(node) {
+            return Some(vec![i]);
+        }
+    }
+    None
+}
+
+/// The crate and its root module start where their first item does; a
+/// comment before that item belongs to the item, not to them.
+fn is_container(node: &AstNode) -> bool {
+    node.ty == "Crate" || node.ty == "Mod"
+}
+
+fn path_to_outermost_starting_at(nodes: &[AstNode], lo: u32) -> Option<Vec<usize>> {
+    for (i, node) in nodes.iter().enumerate() {
+        let starts_here = node.span.map_or(false, |s| s.0 == lo);
+        if starts_here && !is_container(node) {
+            return Some(vec![i]);
+        }
+        if !contains(node, (lo, lo)) {
+            continue;
+        }
+        if let Some(mut path) = path_to_outermost_starting_at(&node.children, lo) {
+            path.insert(0, i);
+            return Some(path);
+        }
+        if starts_here {
+            return Some(vec![i]);
+        }
+    }
+    None
+}
+
+fn node_at_mut<'a>(nodes: &'a mut Vec<AstNode>, path: &[usize]) -> &'a mut AstNode {
+    let mut node = &mut nodes[path[0]];
+    for &i in &path[1..] {
+        node = &mut { node }.children[i];
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use ast_node::AstNode;
+    use tokens::{SourceToken, Trivia};
+    use super::attach_comments;
+
+    fn node(ty: &str, kind: &str, span: (u32, u32), children: Vec<AstNode>) -> AstNode {
+        AstNode {
+            ty: ty.to_owned(),
+            kind: kind.to_owned(),
+            span: Some(span),
+            children: children,
+            ..AstNode::default()
+        }
+    }
+
+    /// Just enough of a lexer for line comments, whitespace, words and
+    /// punctuation.
+    fn lex(src: &str) -> Vec<SourceToken> {
+        let chars: Vec<(usize, char)> = src.char_indices().collect();
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            let start = i;
+            let (kind, trivia) = if src[chars[i].0..].starts_with("//") {
+                while i < chars.len() && chars[i].1 != '\n' {
+                    i += 1;
+                }
+                ("Comment", Some(Trivia::Comment))
+            }
+            else if chars[i].1.is_whitespace() {
+                while i < chars.len() && chars[i].1.is_whitespace() {
+                    i += 1;
+                }
+                ("Whitespace", Some(Trivia::Whitespace))
+            }
+            else if chars[i].1.is_alphanumeric() {
+                while i < chars.len() && chars[i].1.is_alphanumeric() {
+                    i += 1;
+                }
+                ("Ident", None)
+            }
+            else {
+                i += 1;
+                ("Punct", None)
+            };
+            let lo = chars[start].0;
+            let hi = chars.get(i).map_or(src.len(), |&(pos, _)| pos);
+            tokens.push(SourceToken {
+                kind: kind.to_owned(),
+                text: src[lo..hi].to_owned(),
+                span: (lo as u32, hi as u32),
+                trivia: trivia,
+            });
+        }
+        tokens
+    }
+
+    #[test]
+    fn attaches_leading_and_trailing_comments() {
+        let src = "fn a() {}\n// lead\nfn b() {} // trail\n";
+        let mut nodes = vec![
+            node("Mod", "", (0, 27), vec![
+                node("Item", "Fn", (0, 9), vec![node("Block", "", (7, 9), vec![])]),
+                node("Item", "Fn", (18, 27), vec![node("Block", "", (25, 27), vec![])]),
+            ]),
+        ];
+        attach_comments(&mut nodes, &lex(src));
+
+        let item = &nodes[0].children[1];
+        let leading = &item.children[0];
+        assert_eq!((leading.ty.as_str(), leading.kind.as_str()), ("Comment", "Leading"));
+        assert_eq!(leading.span, Some((10, 17)));
+
+        let block = &item.children[1];
+        let trailing = &block.children[0];
+        assert_eq!((trailing.ty.as_str(), trailing.kind.as_str()), ("Comment", "Trailing"));
+        assert_eq!(trailing.span, Some((28, 36)));
+
+        assert!(nodes[0].children[0].children[0].children.is_empty());
+    }
+
+    #[test]
+    fn comments_without_a_neighbour_are_inner() {
+        let src = "fn a() {\n    // x\n}\n";
+        let mut nodes = vec![
+            node("Item", "Fn", (0, 19), vec![node("Block", "", (7, 19), vec![])]),
+        ];
+        attach_comments(&mut nodes, &lex(src));
+
+        let comment = &nodes[0].children[0].children[0];
+        assert_eq!((comment.ty.as_str(), comment.kind.as_str()), ("Comment", "Inner"));
+        assert_eq!(comment.span, Some((13, 17)));
+    }
+
+    #[test]
+    fn keeps_consecutive_leading_comments_in_order() {
+        let src = "// a\n// b\nfn f() {}\n";
+        let mut nodes = vec![
+            node("Item", "Fn", (10, 19), vec![node("Block", "", (17, 19), vec![])]),
+        ];
+        attach_comments(&mut nodes, &lex(src));
+
+        let children: Vec<_> = nodes[0].children.iter().map(|child| (child.ty.as_str(), child.span)).collect();
+        assert_eq!(children, vec![("Comment", Some((0, 4))), ("Comment", Some((5, 9))), ("Block", Some((17, 19)))]);
+    }
+
+    #[test]
+    fn attaches_a_comment_at_the_top_to_the_first_item() {
+        let src = "// top\nfn a() {}\n";
+        let mut nodes = vec![
+            node("Crate", "", (7, 16), vec![
+                node("Mod", "", (7, 16), vec![
+                    node("Item", "Fn", (7, 16), vec![node("Block", "", (14, 16), vec![])]),
+                ]),
+            ]),
+        ];
+        attach_comments(&mut nodes, &lex(src));
+
+        let item = &nodes[0].children[0].children[0];
+        assert_eq!((item.children[0].ty.as_str(), item.children[0].kind.as_str()), ("Comment", "Leading"));
+        assert_eq!(item.span, Some((7, 16)));
+        assert_eq!(nodes[0].children.len(), 1);
+        assert_eq!(nodes[0].children[0].children.len(), 1);
+    }
+}
@@ -1,64 +1,271 @@
+use std::fmt::Debug;
+use syntex_syntax::abi::Abi;
 use syntex_syntax::ast::*;
+use syntex_syntax::codemap::CodeMap;
 use syntex_syntax::visit::*;
+use syntex_syntax::parse::lexer::comments;
+use syntex_syntax::parse::token::Token;
+use syntex_syntax::tokenstream::{TokenStream, TokenTree};
 use syntex_pos::Span;
 use syntex_syntax::print::pprust;
-use gtk::prelude::*;
-use gtk::{ListStore, TreeStore, TreeIter};
-use ast_model_extensions::{AstModelExt, AstStoreExt, AstPropertiesStoreExt};
+use ast_node::{AstNode, Property};
+use tokens::token_kind;
 
-pub(crate) struct TreeVisitor {
-    pub tree: TreeStore,
-    pub iters: Vec<TreeIter>
+pub(crate) struct TreeVisitor<'a> {
+    pub roots: Vec<AstNode>,
+    stack: Vec<AstNode>,
+    codemap: &'a CodeMap
 }
 
 macro_rules! visit {
-    ($self:ident, $props:ident, ($type:expr, $kind:expr) => $walk:block) => {
-        let iter = $self.tree.insert_node($self.iters.last(), $type, $kind, None);
-        let $props = $self.tree.get_properties_list(&iter);
-        $self.iters.push(iter);
+    // Nodes without a span of their own get the union of their children's spans.
+    ($self:ident, ($type:expr, $kind:expr) => $walk:block) => {
+        $self.push(AstNode::new($type, $kind, None));
         $walk;
-        $self.iters.pop();
+        $self.synthesize_span();
+        $self.pop();
     };
-    ($self:ident, $props:ident, ($type:expr, $kind:expr, $span:expr) => $walk:block) => {
-        let iter = $self.tree.insert_node($self.iters.last(), $type, $kind, Some($span));
-        let $props = $self.tree.get_properties_list(&iter);
-        $self.iters.push(iter);
+    ($self:ident, ($type:expr, $kind:expr, $span:expr) => $walk:block) => {
+        $self.push(AstNode::new($type, $kind, Some($span)));
         $walk;
-        $self.iters.pop();
+        $self.pop();
     };
 }
 
-impl TreeVisitor {
-    pub fn new() -> TreeVisitor {
+impl<'a> TreeVisitor<'a> {
+    pub fn new(codemap: &'a CodeMap) -> TreeVisitor<'a> {
         TreeVisitor{
-            tree: TreeStore::new_ast_store(),
-            iters: vec![]
+            roots: vec![],
+            stack: vec![],
+            codemap: codemap
         }
     }
 
+    fn push(&mut self, node: AstNode) {
+        self.stack.push(node);
+    }
+
+    fn pop(&mut self) {
+        let node = self.stack.pop().expect("Unbalanced TreeVisitor stack");
+        match self.stack.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.roots.push(node),
+        }
+    }
+
+    fn synthesize_span(&mut self) {
+        let node = match self.stack.last_mut() {
+            Some(node) => node,
+            None => return,
+        };
+        let span = node.children.iter()
+            .filter_map(|child| child.span)
+            .fold(None, |acc: Option<(u32, u32)>, (lo, hi)| match acc {
+                Some((acc_lo, acc_hi)) => Some((acc_lo.min(lo), acc_hi.max(hi))),
+                None => Some((lo, hi)),
+            });
+        if span.is_some() {
+            node.span = span;
+            node.add_property(Property::new("SpanOrigin", "Synthesized from children"));
+        }
+    }
+
+    fn add_property(&mut self, property: Property) {
+        if let Some(node) = self.stack.last_mut() {
+            node.add_property(property);
+        }
+    }
+
+    fn property(&mut self, name: &str, value: &str) {
+        self.add_property(Property::new(name, value));
+    }
+
+    fn debug_property<T: Debug>(&mut self, name: &str, value: &T) {
+        self.add_property(Property::new(name, &format!("{:?}", value)));
+    }
+
+    fn node_id(&mut self, id: NodeId) {
+        self.property("NodeId", &id.to_string());
+    }
+
+    fn ident_property(&mut self, name: &str, ident: Ident) {
+        self.property(name, &pprust::ident_to_string(ident));
+    }
+
+    /// The root of a crate's tree: its span and inner attributes, with the
+    /// root module underneath.
+    pub fn visit_crate(&mut self, krate: &Crate) {
+        visit!(self, ("Crate", "", krate.span) => {
+            for attr in &krate.attrs {
+                self.visit_attribute(attr);
+            }
+            self.visit_mod(&krate.module, krate.span, &krate.attrs, CRATE_NODE_ID);
+        });
+    }
+
     fn _visit_path(&mut self, path: &Path) {
-        visit!(self, path_props, ("Path", "", path.span) => {
+        visit!(self, ("Path", "", path.span) => {
             walk_path(self, path);
         });
     }
+
+    fn _visit_token_stream(&mut self, stream: TokenStream) {
+        for tree in stream.trees() {
+            self._visit_token_tree(tree);
+        }
+    }
+
+    fn _visit_token_tree(&mut self, tree: TokenTree) {
+        match tree {
+            TokenTree::Token(span, token) => {
+                visit!(self, ("TokenTree", "Token", span) => {
+                    self.property("Token", &token_kind(&token));
+                    self.property("Text", &pprust::token_to_string(&token));
+                });
+            }
+            TokenTree::Delimited(span, delimited) => {
+                visit!(self, ("TokenTree", "Delimited", span) => {
+                    self.debug_property("Delimiter", &delimited.delim);
+                    self._visit_token_stream(delimited.stream());
+                });
+            }
+        }
+    }
+
+    /// Splits a `macro_rules!` body into `(matcher) => {transcriber}` rules.
+    /// Anything that doesn't look like a rule is shown as plain token trees.
+    fn _visit_macro_rules(&mut self, stream: TokenStream) {
+        let trees: Vec<TokenTree> = stream.trees().collect();
+        let mut i = 0;
+        while i < trees.len() {
+            let is_rule = match (trees.get(i), trees.get(i + 1), trees.get(i + 2)) {
+                (Some(&TokenTree::Delimited(..)),
+                 Some(&TokenTree::Token(_, Token::FatArrow)),
+                 Some(&TokenTree::Delimited(..))) => true,
+                _ => false,
+            };
+            if !is_rule {
+                self._visit_token_tree(trees[i].clone());
+                i += 1;
+                continue;
+            }
+
+            visit!(self, ("MacroRule", "") => {
+                visit!(self, ("Matcher", "") => {
+                    self._visit_token_tree(trees[i].clone());
+                });
+                self._visit_token_tree(trees[i + 1].clone());
+                visit!(self, ("Transcriber", "") => {
+                    self._visit_token_tree(trees[i + 2].clone());
+                });
+            });
+            i += 3;
+        }
+    }
+
+    /// Macro invocations don't keep their delimiter, so look at the source.
+    fn mac_delimiter(&self, span: Span) -> Option<&'static str> {
+        let snippet = self.codemap.span_to_snippet(span).ok()?;
+        match snippet.trim_right().chars().last() {
+            Some(')') => Some("Paren"),
+            Some(']') => Some("Bracket"),
+            Some('}') => Some("Brace"),
+            _ => None,
+        }
+    }
+}
+
+fn debug_string<T: Debug>(value: &T) -> String {
+    format!("{:?}", value)
+}
+
+/// `unsafe const extern "C"`-like summary of a function header,
+/// with the individual qualifiers as children.
+fn fn_header(unsafety: Unsafety, constness: Constness, abi: Abi) -> Property {
+    let mut summary = vec![];
+    if unsafety == Unsafety::Unsafe {
+        summary.push("unsafe".to_owned());
+    }
+    if constness == Constness::Const {
+        summary.push("const".to_owned());
+    }
+    if abi != Abi::Rust {
+        summary.push(format!("extern \"{}\"", abi.name()));
+    }
+
+    Property::new("Header", &summary.join(" "))
+        .with_child("Unsafety", &debug_string(&unsafety))
+        .with_child("Constness", &debug_string(&constness))
+        .with_child("Abi", abi.name())
+}
+
+fn binding_mode(mode: BindingMode) -> Property {
+    match mode {
+        BindingMode::ByRef(mutbl) => Property::new("BindingMode", "ByRef")
+            .with_child("Mutability", &debug_string(&mutbl)),
+        BindingMode::ByValue(mutbl) => Property::new("BindingMode", "ByValue")
+            .with_child("Mutability", &debug_string(&mutbl)),
+    }
+}
+
+fn literal(lit: &Lit) -> Property {
+    let property = Property::new("Literal", &pprust::lit_to_string(lit));
+    match lit.node {
+        LitKind::Str(ref value, style) => property
+            .with_child("LitKind", "Str")
+            .with_child("Value", &value.as_str())
+            .with_child("StrStyle", &debug_string(&style)),
+        LitKind::ByteStr(ref value) => property
+            .with_child("LitKind", "ByteStr")
+            .with_child("Value", &String::from_utf8_lossy(value)),
+        LitKind::Byte(value) => property
+            .with_child("LitKind", "Byte")
+            .with_child("Value", &value.to_string()),
+        LitKind::Char(value) => property
+            .with_child("LitKind", "Char")
+            .with_child("Value", &value.escape_default().collect::<String>()),
+        LitKind::Int(value, ty) => {
+            let suffix = match ty {
+                LitIntType::Signed(ty) => ty.ty_to_string(),
+                LitIntType::Unsigned(ty) => ty.ty_to_string(),
+                LitIntType::Unsuffixed => "",
+            };
+            property
+                .with_child("LitKind", "Int")
+                .with_child("Value", &value.to_string())
+                .with_child("Suffix", suffix)
+        }
+        LitKind::Float(ref value, ty) => property
+            .with_child("LitKind", "Float")
+            .with_child("Value", &value.as_str())
+            .with_child("Suffix", ty.ty_to_string()),
+        LitKind::FloatUnsuffixed(ref value) => property
+            .with_child("LitKind", "Float")
+            .with_child("Value", &value.as_str())
+            .with_child("Suffix", ""),
+        LitKind::Bool(value) => property
+            .with_child("LitKind", "Bool")
+            .with_child("Value", &value.to_string()),
+    }
 }
 
-impl<'ast> Visitor<'ast> for TreeVisitor {
+impl<'a, 'ast> Visitor<'ast> for TreeVisitor<'a> {
     fn visit_name(&mut self, span: Span, name: Name) {
-        let iter = self.tree.insert_node(self.iters.last(), "Name", "", Some(span));
-        let props = self.tree.get_properties_list(&iter);
-        props.insert_property("Name", &name.as_str());
+        self.push(AstNode::new("Name", "", Some(span)));
+        self.property("Name", &name.as_str());
+        self.pop();
     }
 
     fn visit_ident(&mut self, span: Span, ident: Ident) {
-        visit!(self, props, ("Ident", "", span) => {
-            props.insert_property("Name", &pprust::ident_to_string(ident));
+        visit!(self, ("Ident", "", span) => {
+            self.ident_property("Name", ident);
             walk_ident(self, span, ident);
         });
     }
 
-    fn visit_mod(&mut self, m: &'ast Mod, _span: Span, _attrs: &[Attribute], _n: NodeId) {
-        visit!(self, props, ("Mod", "", m.inner)/*span*/ => {
+    fn visit_mod(&mut self, m: &'ast Mod, _span: Span, _attrs: &[Attribute], n: NodeId) {
+        visit!(self, ("Mod", "", m.inner)/*span*/ => {
+            self.node_id(n);
             walk_mod(self, m);
         });
     }
@@ -66,7 +273,17 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
     // fn visit_global_asm(&mut self, ga: &'ast GlobalAsm) { walk_global_asm(self, ga) }
 
     fn visit_foreign_item(&mut self, i: &'ast ForeignItem) {
-        visit!(self, props, ("ForeignItem", "") => {
+        let kind = match i.node {
+            ForeignItemKind::Fn(..) => "Fn",
+            ForeignItemKind::Static(..) => "Static",
+        };
+        visit!(self, ("ForeignItem", kind, i.span) => {
+            self.node_id(i.id);
+            self.ident_property("Name", i.ident);
+            if let ForeignItemKind::Static(_, mutable) = i.node {
+                let mutbl = if mutable { Mutability::Mutable } else { Mutability::Immutable };
+                self.debug_property("Mutability", &mutbl);
+            }
             walk_foreign_item(self, i);
         });
     }
@@ -91,19 +308,46 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             ItemKind::Mac(..) => "Mac",
             ItemKind::MacroDef(..) => "MacroDef",
         };
-        visit!(self, props, ("Item", kind) => {
+        visit!(self, ("Item", kind, i.span) => {
+            self.node_id(i.id);
+            self.ident_property("Name", i.ident);
+            match i.node {
+                ItemKind::ExternCrate(Some(original)) => {
+                    self.property("OriginalName", &original.as_str());
+                }
+                ItemKind::Static(_, mutbl, _) => {
+                    self.debug_property("Mutability", &mutbl);
+                }
+                ItemKind::Fn(_, unsafety, constness, abi, ..) => {
+                    self.add_property(fn_header(unsafety, constness.node, abi));
+                }
+                ItemKind::ForeignMod(ref foreign_mod) => {
+                    self.property("Abi", foreign_mod.abi.name());
+                }
+                ItemKind::Trait(unsafety, ..) | ItemKind::DefaultImpl(unsafety, ..) => {
+                    self.debug_property("Unsafety", &unsafety);
+                }
+                ItemKind::Impl(unsafety, polarity, ..) => {
+                    self.debug_property("Unsafety", &unsafety);
+                    self.debug_property("ImplPolarity", &polarity);
+                }
+                _ => {}
+            }
             walk_item(self, i);
         });
     }
 
     fn visit_local(&mut self, l: &'ast Local) {
-        visit!(self, props, ("Local", "", l.span) => {
+        visit!(self, ("Local", "", l.span) => {
+            self.node_id(l.id);
             walk_local(self, l);
         });
     }
 
     fn visit_block(&mut self, b: &'ast Block) {
-        visit!(self, props, ("Block", "", b.span) => {
+        visit!(self, ("Block", "", b.span) => {
+            self.node_id(b.id);
+            self.debug_property("Rules", &b.rules);
             walk_block(self, b);
         });
     }
@@ -116,13 +360,16 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             StmtKind::Semi(..) => "Semi",
             StmtKind::Mac(..) => "Mac",
         };
-        visit!(self, props, ("Stmt", kind, s.span) => {
+        visit!(self, ("Stmt", kind, s.span) => {
+            self.node_id(s.id);
             walk_stmt(self, s);
         });
     }
 
     fn visit_arm(&mut self, a: &'ast Arm) {
-        visit!(self, props, ("Arm", "") => {
+        visit!(self, ("Arm", "") => {
+            self.property("Patterns", &a.pats.len().to_string());
+            self.property("HasGuard", &a.guard.is_some().to_string());
             walk_arm(self, a);
         });
     }
@@ -142,7 +389,26 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             PatKind::Slice(..) => "Slice",
             PatKind::Mac(..) => "Mac",
         };
-        visit!(self, props, ("Pat", kind, p.span) => {
+        visit!(self, ("Pat", kind, p.span) => {
+            self.node_id(p.id);
+            match p.node {
+                PatKind::Ident(mode, ..) => {
+                    self.add_property(binding_mode(mode));
+                }
+                PatKind::Struct(_, _, has_rest) => {
+                    self.property("HasRest", &has_rest.to_string());
+                }
+                PatKind::TupleStruct(_, _, Some(rest)) | PatKind::Tuple(_, Some(rest)) => {
+                    self.property("RestPosition", &rest.to_string());
+                }
+                PatKind::Ref(_, mutbl) => {
+                    self.debug_property("Mutability", &mutbl);
+                }
+                PatKind::Range(_, _, end) => {
+                    self.debug_property("RangeEnd", &end);
+                }
+                _ => {}
+            }
             walk_pat(self, p);
         });
     }
@@ -188,7 +454,40 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             ExprKind::Paren(..) => "Paren",
             ExprKind::Try(..) => "Try",
         };
-        visit!(self, props, ("Expr", kind, ex.span) => {
+        visit!(self, ("Expr", kind, ex.span) => {
+            self.node_id(ex.id);
+            match ex.node {
+                ExprKind::Binary(op, ..) | ExprKind::AssignOp(op, ..) => {
+                    self.add_property(Property::new("BinOpKind", &debug_string(&op.node))
+                        .with_child("Operator", op.node.to_string()));
+                }
+                ExprKind::Unary(op, _) => {
+                    self.add_property(Property::new("UnOp", &debug_string(&op))
+                        .with_child("Operator", UnOp::to_string(op)));
+                }
+                ExprKind::Lit(ref lit) => {
+                    self.add_property(literal(lit));
+                }
+                ExprKind::Closure(capture_by, ..) => {
+                    self.debug_property("CaptureBy", &capture_by);
+                }
+                ExprKind::AddrOf(mutbl, _) => {
+                    self.debug_property("Mutability", &mutbl);
+                }
+                ExprKind::MethodCall(ident, ..) => {
+                    self.ident_property("Name", ident.node);
+                }
+                ExprKind::Field(_, ident) => {
+                    self.ident_property("Field", ident.node);
+                }
+                ExprKind::TupField(_, index) => {
+                    self.property("Index", &index.node.to_string());
+                }
+                ExprKind::Range(_, _, limits) => {
+                    self.debug_property("RangeLimits", &limits);
+                }
+                _ => {}
+            }
             walk_expr(self, ex);
         });
     }
@@ -216,106 +515,191 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             TyKind::Mac(..) => "Mac",
             TyKind::Err => "Err",
         };
-        visit!(self, props, ("Ty", kind, t.span) => {
+        visit!(self, ("Ty", kind, t.span) => {
+            self.node_id(t.id);
+            match t.node {
+                TyKind::Ptr(ref mut_ty) | TyKind::Rptr(_, ref mut_ty) => {
+                    self.debug_property("Mutability", &mut_ty.mutbl);
+                }
+                TyKind::BareFn(ref bare_fn) => {
+                    self.debug_property("Unsafety", &bare_fn.unsafety);
+                    self.property("Abi", bare_fn.abi.name());
+                }
+                _ => {}
+            }
             walk_ty(self, t);
         });
     }
 
     fn visit_generics(&mut self, g: &'ast Generics) {
-        visit!(self, props, ("Generics", "", g.span) => {
+        visit!(self, ("Generics", "", g.span) => {
+            self.property("Lifetimes", &g.lifetimes.len().to_string());
+            self.property("TyParams", &g.ty_params.len().to_string());
+            self.property("WherePredicates", &g.where_clause.predicates.len().to_string());
             walk_generics(self, g);
         });
     }
 
     fn visit_where_predicate(&mut self, p: &'ast WherePredicate) {
-        visit!(self, props, ("WherePredicate", "") => {
+        let (kind, span) = match *p {
+            WherePredicate::BoundPredicate(ref bound) => ("BoundPredicate", bound.span),
+            WherePredicate::RegionPredicate(ref region) => ("RegionPredicate", region.span),
+            WherePredicate::EqPredicate(ref eq) => ("EqPredicate", eq.span),
+        };
+        visit!(self, ("WherePredicate", kind, span) => {
             walk_where_predicate(self, p);
         });
     }
 
-    fn visit_fn(&mut self, fk: FnKind<'ast>, fd: &'ast FnDecl, s: Span, _: NodeId) {
+    fn visit_fn(&mut self, fk: FnKind<'ast>, fd: &'ast FnDecl, s: Span, id: NodeId) {
         let kind = match fk {
             FnKind::ItemFn(..) => "ItemFn",
             FnKind::Method(..) => "Method",
             FnKind::Closure(..) => "Closure",
         };
-        visit!(self, props, ("Fn", kind, s) => {
+        visit!(self, ("Fn", kind, s) => {
+            self.node_id(id);
+            match fk {
+                FnKind::ItemFn(ident, _, unsafety, constness, abi, ..) => {
+                    self.ident_property("Name", ident);
+                    self.add_property(fn_header(unsafety, constness.node, abi));
+                }
+                FnKind::Method(ident, sig, ..) => {
+                    self.ident_property("Name", ident);
+                    self.add_property(fn_header(sig.unsafety, sig.constness.node, sig.abi));
+                }
+                FnKind::Closure(..) => {}
+            }
+            self.property("Variadic", &fd.variadic.to_string());
             walk_fn(self, fk, fd, s);
         });
     }
 
     fn visit_trait_item(&mut self, ti: &'ast TraitItem) {
-        visit!(self, props, ("TraitItem", "", ti.span) => {
+        let kind = match ti.node {
+            TraitItemKind::Const(..) => "Const",
+            TraitItemKind::Method(..) => "Method",
+            TraitItemKind::Type(..) => "Type",
+            TraitItemKind::Macro(..) => "Macro",
+        };
+        visit!(self, ("TraitItem", kind, ti.span) => {
+            self.node_id(ti.id);
+            self.ident_property("Name", ti.ident);
             walk_trait_item(self, ti);
         });
     }
 
     fn visit_impl_item(&mut self, ii: &'ast ImplItem) {
-        visit!(self, props, ("ImplItem", "", ii.span) => {
+        let kind = match ii.node {
+            ImplItemKind::Const(..) => "Const",
+            ImplItemKind::Method(..) => "Method",
+            ImplItemKind::Type(..) => "Type",
+            ImplItemKind::Macro(..) => "Macro",
+        };
+        visit!(self, ("ImplItem", kind, ii.span) => {
+            self.node_id(ii.id);
+            self.ident_property("Name", ii.ident);
+            self.debug_property("Defaultness", &ii.defaultness);
             walk_impl_item(self, ii);
         });
     }
 
     fn visit_trait_ref(&mut self, t: &'ast TraitRef) {
-        visit!(self, props, ("TraitRef", "") => {
+        visit!(self, ("TraitRef", "") => {
+            self.node_id(t.ref_id);
             walk_trait_ref(self, t);
         });
     }
 
     fn visit_ty_param_bound(&mut self, bounds: &'ast TyParamBound) {
-        visit!(self, props, ("TyParamBound", "") => {
+        let (kind, span) = match *bounds {
+            TyParamBound::TraitTyParamBound(ref poly_trait_ref, _) => ("TraitTyParamBound", poly_trait_ref.span),
+            TyParamBound::RegionTyParamBound(ref lifetime) => ("RegionTyParamBound", lifetime.span),
+        };
+        visit!(self, ("TyParamBound", kind, span) => {
             walk_ty_param_bound(self, bounds);
         });
     }
 
     fn visit_poly_trait_ref(&mut self, t: &'ast PolyTraitRef, m: &'ast TraitBoundModifier) {
-        visit!(self, props, ("PolyTraitRef", "") => {
+        visit!(self, ("PolyTraitRef", "", t.span) => {
+            self.debug_property("Modifier", m);
             walk_poly_trait_ref(self, t, m);
         });
     }
 
-    fn visit_variant_data(&mut self, s: &'ast VariantData, _: Ident,
-                          _: &'ast Generics, _: NodeId, span: Span) {
-        visit!(self, props, ("VariantData", "", span) => {
+    fn visit_variant_data(&mut self, s: &'ast VariantData, ident: Ident,
+                          _: &'ast Generics, id: NodeId, span: Span) {
+        let kind = match *s {
+            VariantData::Struct(..) => "Struct",
+            VariantData::Tuple(..) => "Tuple",
+            VariantData::Unit(..) => "Unit",
+        };
+        visit!(self, ("VariantData", kind, span) => {
+            self.node_id(id);
+            self.ident_property("Name", ident);
             walk_struct_def(self, s);
         });
     }
 
     fn visit_struct_field(&mut self, s: &'ast StructField) {
-        visit!(self, props, ("StructField", "", s.span) => {walk_struct_field(self, s)});
+        visit!(self, ("StructField", "", s.span) => {
+            self.node_id(s.id);
+            if let Some(ident) = s.ident {
+                self.ident_property("Name", ident);
+            }
+            walk_struct_field(self, s);
+        });
     }
 
     fn visit_enum_def(&mut self, enum_definition: &'ast EnumDef,
                       generics: &'ast Generics, item_id: NodeId, span: Span) {
-        visit!(self, props, ("EnumDef", "", span) => {
+        visit!(self, ("EnumDef", "", span) => {
+            self.property("Variants", &enum_definition.variants.len().to_string());
             walk_enum_def(self, enum_definition, generics, item_id);
         });
     }
 
     fn visit_variant(&mut self, v: &'ast Variant, g: &'ast Generics, item_id: NodeId) {
-        visit!(self, props, ("Variant", "") => {
+        visit!(self, ("Variant", "", v.span) => {
+            self.ident_property("Name", v.node.name);
+            self.property("HasDiscriminant", &v.node.disr_expr.is_some().to_string());
             walk_variant(self, v, g, item_id);
         });
     }
 
     fn visit_lifetime(&mut self, lifetime: &'ast Lifetime) {
-        visit!(self, props, ("Lifetime", "", lifetime.span) => {
+        visit!(self, ("Lifetime", "", lifetime.span) => {
+            self.node_id(lifetime.id);
             walk_lifetime(self, lifetime);
         });
     }
 
     fn visit_lifetime_def(&mut self, lifetime: &'ast LifetimeDef) {
-        visit!(self, props, ("LifetimeDef", "") => {
+        visit!(self, ("LifetimeDef", "") => {
+            self.property("Bounds", &lifetime.bounds.len().to_string());
             walk_lifetime_def(self, lifetime);
         });
     }
 
-    fn visit_mac(&mut self, _mac: &'ast Mac) {
-        // visit::walk_mac(self, _mac)
+    fn visit_mac(&mut self, mac: &'ast Mac) {
+        visit!(self, ("Mac", "", mac.span) => {
+            self.property("Path", &pprust::path_to_string(&mac.node.path));
+            if let Some(delimiter) = self.mac_delimiter(mac.span) {
+                self.property("Delimiter", delimiter);
+            }
+            self._visit_path(&mac.node.path);
+            self._visit_token_stream(mac.node.stream());
+        });
     }
 
-    fn visit_mac_def(&mut self, _mac: &'ast MacroDef, _id: NodeId) {
-        // Nothing to do
+    fn visit_mac_def(&mut self, mac: &'ast MacroDef, id: NodeId) {
+        let kind = if mac.legacy { "MacroRules" } else { "Macro" };
+        visit!(self, ("MacroDef", kind) => {
+            self.node_id(id);
+            self.property("Legacy", &mac.legacy.to_string());
+            self._visit_macro_rules(mac.stream());
+        });
     }
 
     fn visit_path(&mut self, path: &'ast Path, _id: NodeId) {
@@ -323,32 +707,53 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
     }
 
     fn visit_path_list_item(&mut self, prefix: &'ast Path, item: &'ast PathListItem) {
-        visit!(self, props, ("ListItem", "") => {
+        visit!(self, ("ListItem", "", item.span) => {
+            self.node_id(item.node.id);
+            if let Some(rename) = item.node.rename {
+                self.ident_property("Rename", rename);
+            }
             walk_path_list_item(self, prefix, item);
         });
     }
 
     fn visit_path_segment(&mut self, path_span: Span, path_segment: &'ast PathSegment) {
-        visit!(self, props, ("PathSegment", "", path_segment.span) => {
+        visit!(self, ("PathSegment", "", path_segment.span) => {
             walk_path_segment(self, path_span, path_segment);
         });
     }
 
     fn visit_path_parameters(&mut self, path_span: Span, path_parameters: &'ast PathParameters) {
         //FIXME: add span if match Parenthesized()
-        visit!(self, props, ("PathParameters", "", path_span) => {
+        let kind = match *path_parameters {
+            PathParameters::AngleBracketed(..) => "AngleBracketed",
+            PathParameters::Parenthesized(..) => "Parenthesized",
+        };
+        visit!(self, ("PathParameters", kind, path_span) => {
             walk_path_parameters(self, path_span, path_parameters);
         });
     }
 
     fn visit_assoc_type_binding(&mut self, type_binding: &'ast TypeBinding) {
-        visit!(self, props, ("TypeBinding", "") => {
+        visit!(self, ("TypeBinding", "", type_binding.span) => {
+            self.node_id(type_binding.id);
+            self.ident_property("Name", type_binding.ident);
             walk_assoc_type_binding(self, type_binding);
         });
     }
 
     fn visit_attribute(&mut self, attr: &'ast Attribute) {
-        visit!(self, attr_props, ("Attribute", "", attr.span) => {
+        let kind = if attr.is_sugared_doc { "DocComment" } else { "" };
+        visit!(self, ("Attribute", kind, attr.span) => {
+            self.debug_property("Style", &attr.style);
+            self.property("IsSugaredDoc", &attr.is_sugared_doc.to_string());
+            if attr.is_sugared_doc {
+                if let Ok(sugared) = self.codemap.span_to_snippet(attr.span) {
+                    self.property("Sugared", &sugared);
+                }
+                if let Some(text) = attr.value_str() {
+                    self.property("Text", &comments::strip_doc_comment_decoration(&text.as_str()));
+                }
+            }
             self._visit_path(&attr.path);
         });
     }
@@ -360,13 +765,27 @@ impl<'ast> Visitor<'ast> for TreeVisitor {
             Visibility::Public => "Public",
             Visibility::Restricted{..} => "Restricted"
         };
-        visit!(self, props, ("Vis", kind) => {
-            walk_vis(self, vis);
-        });
+        if let Visibility::Crate(span) = *vis {
+            visit!(self, ("Vis", kind, span) => {
+                walk_vis(self, vis);
+            });
+        }
+        else {
+            visit!(self, ("Vis", kind) => {
+                if let Visibility::Restricted { id, .. } = *vis {
+                    self.node_id(id);
+                }
+                walk_vis(self, vis);
+            });
+        }
     }
 
     fn visit_fn_ret_ty(&mut self, ret_ty: &'ast FunctionRetTy) {
-        visit!(self, props, ("FnRetTy", "", ret_ty.span()) => {
+        let kind = match *ret_ty {
+            FunctionRetTy::Default(..) => "Default",
+            FunctionRetTy::Ty(..) => "Ty",
+        };
+        visit!(self, ("FnRetTy", kind, ret_ty.span()) => {
             walk_fn_ret_ty(self, ret_ty);
         });
     }